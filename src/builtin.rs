@@ -0,0 +1,158 @@
+use crate::error::RuntimeError;
+use crate::object::Object;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+pub type BuiltinFn = fn(&[Object]) -> Result<Object, RuntimeError>;
+
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object, RuntimeError>;
+
+// Function implemented in Rust rather than in a script, either by the
+// interpreter or by the host through Environment::register_fn
+pub struct Builtin {
+    pub name: String,
+    pub func: Rc<NativeFn>,
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builtin")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Builtins every global environment starts with, looked up after the script's
+// own variables so a script can shadow them
+pub fn registry() -> HashMap<String, Rc<Builtin>> {
+    let builtins: [(&str, BuiltinFn); 8] = [
+        ("print", print),
+        ("println", println),
+        ("len", len),
+        ("type", type_of),
+        ("str", str),
+        ("repr", repr),
+        ("num", num),
+        ("input", input),
+    ];
+    builtins
+        .into_iter()
+        .map(|(name, func)| {
+            let name = name.to_string();
+            let func = Rc::new(func);
+            (name.clone(), Rc::new(Builtin { name, func }))
+        })
+        .collect()
+}
+
+// Check the number of arguments is within min..=max
+fn arity(args: &[Object], min: usize, max: usize) -> Result<(), RuntimeError> {
+    if args.len() < min || args.len() > max {
+        return Err(RuntimeError::ArgumentCount {
+            expected: if args.len() < min { min } else { max },
+            found: args.len(),
+        });
+    }
+    Ok(())
+}
+
+fn invalid_argument(function: &str, expected: &'static str, found: &Object) -> RuntimeError {
+    RuntimeError::InvalidArgument {
+        function: function.to_string(),
+        expected,
+        found: found.clone(),
+    }
+}
+
+// Arguments joined by spaces
+fn join(args: &[Object]) -> String {
+    args.iter()
+        .map(|arg| arg.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn print(args: &[Object]) -> Result<Object, RuntimeError> {
+    print!("{}", join(args));
+    io::stdout().flush().ok();
+    Ok(Object::Null)
+}
+
+fn println(args: &[Object]) -> Result<Object, RuntimeError> {
+    println!("{}", join(args));
+    Ok(Object::Null)
+}
+
+fn len(args: &[Object]) -> Result<Object, RuntimeError> {
+    arity(args, 1, 1)?;
+    let len = match &args[0] {
+        Object::String(s) => s.chars().count(),
+        Object::Array(elements) => elements.borrow().len(),
+        Object::Hash(hash) => hash.borrow().len(),
+        arg => return Err(invalid_argument("len", "a string, array or hash", arg)),
+    };
+    Ok(Object::Integer(len as i64))
+}
+
+fn type_of(args: &[Object]) -> Result<Object, RuntimeError> {
+    arity(args, 1, 1)?;
+    Ok(Object::String(args[0].type_name().to_string()))
+}
+
+fn str(args: &[Object]) -> Result<Object, RuntimeError> {
+    arity(args, 1, 1)?;
+    Ok(Object::String(args[0].to_string()))
+}
+
+fn repr(args: &[Object]) -> Result<Object, RuntimeError> {
+    arity(args, 1, 1)?;
+    Ok(Object::String(args[0].repr().to_string()))
+}
+
+// Strings are parsed the same way as number literals: an integer unless they
+// contain a fraction or exponent
+fn num(args: &[Object]) -> Result<Object, RuntimeError> {
+    arity(args, 1, 1)?;
+    match &args[0] {
+        Object::Integer(_) | Object::Number(_) => Ok(args[0].clone()),
+        Object::Boolean(b) => Ok(Object::Integer(*b as i64)),
+        Object::String(s) => {
+            let s = s.trim();
+            if let Ok(i) = s.parse() {
+                return Ok(Object::Integer(i));
+            }
+            s.parse()
+                .map(Object::Number)
+                .map_err(|_| invalid_argument("num", "a numeric string", &args[0]))
+        }
+        arg => Err(invalid_argument("num", "a number, string or boolean", arg)),
+    }
+}
+
+// Read a line from stdin without its line ending, null at the end of input
+fn input(args: &[Object]) -> Result<Object, RuntimeError> {
+    arity(args, 0, 1)?;
+    if let Some(prompt) = args.first() {
+        print!("{}", prompt);
+        io::stdout().flush().ok();
+    }
+
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => Ok(Object::Null),
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(Object::String(line))
+        }
+    }
+}
@@ -0,0 +1,1106 @@
+// Lowers a parsed program to bytecode for the vm. Locals of a function live
+// in numbered slots of its frame, globals by their number in the global
+// environment so they are shared with the host and the evaluator
+use crate::error::{CompileError, InterpreterError, RuntimeError};
+use crate::object::{Globals, Object};
+use crate::parser::Program;
+use crate::span::{Span, Spanned};
+use crate::statement::{Binding, ExpressionStatement, Statement};
+use crate::token::Token;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::mem;
+use std::rc::Rc;
+
+// Operands index the constant pool (u16), a local slot (u16), a capture of
+// the running closure (u16), a global (u16) or the code of the chunk (u32)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(u16),
+    Null,
+    Pop,
+    Dup,
+    // Duplicate the two values on top of the stack
+    Dup2,
+    GetLocal(u16),
+    SetLocal(u16),
+    GetUpvalue(u16),
+    SetUpvalue(u16),
+    // Globals are numbered by Globals::index
+    GetGlobal(u16),
+    SetGlobal(u16),
+    DefineGlobal(u16),
+    // Move the captured locals from this slot up out of the frame
+    CloseUpvalues(u16),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    FloorDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Equal,
+    NotEqual,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Not,
+    Neg,
+    BitNot,
+    Jump(u32),
+    JumpIfFalse(u32),
+    Array(u16),
+    Hash(u16),
+    Index,
+    SetIndex,
+    // Whether each bound of the slice is on the stack
+    Slice { start: bool, end: bool },
+    GetField(u16),
+    SetField(u16),
+    Range,
+    // Check the two bounds of a range a for loop counts through
+    CheckRange,
+    // Replace an iterable with the array of values a for loop visits
+    IterValues,
+    // Push the next value of the loop whose state starts at slot, or jump
+    // to exit once there are none left
+    ForNext { slot: u16, exit: u32 },
+    // Create a closure of one of the chunk's functions
+    Closure(u16),
+    Call(u8),
+    TailCall(u8),
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    // Source of each instruction, for error locations
+    pub spans: Vec<Span>,
+    pub constants: Vec<Object>,
+    pub functions: Vec<Rc<CompiledFunction>>,
+}
+
+// Where a new closure takes a captured variable from: a local slot of the
+// function creating it, or one of that function's own captures
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Local(u16),
+    Upvalue(u16),
+}
+
+#[derive(Debug)]
+pub struct CompiledFunction {
+    pub name: String,
+    pub params: Vec<String>,
+    // Number of local slots a frame of this function needs
+    pub slot_count: usize,
+    pub captures: Vec<Capture>,
+    pub chunk: Chunk,
+}
+
+struct Local {
+    slot: u16,
+    captured: bool,
+}
+
+struct Scope {
+    // In the order they are declared, as the resolver numbers them
+    locals: Vec<Local>,
+    first_slot: u16,
+    // Scope of the compiler's own slots, which the resolver does not count
+    hidden: bool,
+}
+
+struct Loop {
+    label: Option<String>,
+    // Where continue jumps to
+    start: usize,
+    // Operand stack depth at start, break and continue pop down to it
+    depth: usize,
+    first_slot: u16,
+    breaks: Vec<usize>,
+}
+
+// Constants are stored once per function. Floats are told apart by their
+// bits, unlike with PartialEq 0.0 and -0.0 are different constants
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Integer(i64),
+    Number(u64),
+    String(String),
+    Boolean(bool),
+}
+
+impl ConstantKey {
+    fn new(value: &Object) -> Self {
+        match value {
+            Object::Integer(num) => Self::Integer(*num),
+            Object::Number(num) => Self::Number(num.to_bits()),
+            Object::String(s) => Self::String(s.clone()),
+            Object::Boolean(b) => Self::Boolean(*b),
+            v => unreachable!("{:?} is not a constant", v),
+        }
+    }
+}
+
+struct FunctionState {
+    function: CompiledFunction,
+    // Index of every constant in the chunk's pool
+    constants: HashMap<ConstantKey, usize>,
+    scopes: Vec<Scope>,
+    loops: Vec<Loop>,
+    next_slot: u16,
+    // Values on the operand stack of the frame at this point of the code
+    depth: usize,
+    script: bool,
+}
+
+impl FunctionState {
+    fn new(name: String, params: Vec<String>, script: bool) -> Self {
+        Self {
+            function: CompiledFunction {
+                name,
+                params,
+                slot_count: 0,
+                captures: vec![],
+                chunk: Chunk::default(),
+            },
+            constants: HashMap::new(),
+            scopes: vec![],
+            loops: vec![],
+            next_slot: 0,
+            depth: 0,
+            script,
+        }
+    }
+}
+
+enum Variable {
+    Local(u16),
+    Upvalue(u16),
+    Global(u16),
+}
+
+struct Compiler<'a> {
+    // Function being compiled last, the functions it is nested in before it
+    states: Vec<FunctionState>,
+    // Globals of the environment the program will run in
+    globals: &'a mut Globals,
+    // Source of the node being compiled
+    span: Span,
+}
+
+// The program must have been through resolver::resolve, which rejects what
+// the evaluator would and binds identifiers to their scope
+pub fn compile(
+    program: &Program,
+    globals: &mut Globals,
+) -> Result<Rc<CompiledFunction>, InterpreterError> {
+    let mut compiler = Compiler {
+        states: vec![FunctionState::new("<script>".to_string(), vec![], true)],
+        globals,
+        span: Span::default(),
+    };
+    compiler.statements(&program.statements, false)?;
+    if let Some(last) = program.statements.last() {
+        compiler.span = last.span;
+    }
+    compiler.emit(Op::Return);
+    let state = compiler.states.pop().expect("script state");
+    Ok(Rc::new(state.function))
+}
+
+impl Compiler<'_> {
+    fn state(&self) -> &FunctionState {
+        self.states.last().expect("function state")
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().expect("function state")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state_mut().function.chunk
+    }
+
+    fn runtime_error(&self, e: RuntimeError) -> InterpreterError {
+        InterpreterError::Runtime(e, Some(self.span))
+    }
+
+    fn limit_error(&self, what: &'static str) -> InterpreterError {
+        InterpreterError::Compile(CompileError::LimitExceeded(what), self.span)
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let span = self.span;
+        let state = self.state_mut();
+        state.depth = (state.depth as isize + Self::stack_effect(op)) as usize;
+        state.function.chunk.code.push(op);
+        state.function.chunk.spans.push(span);
+        state.function.chunk.code.len() - 1
+    }
+
+    // Change in operand stack depth once op has run and execution goes on
+    // to the next instruction. Return and TailCall do not go on, the
+    // statement they end still counts as leaving one value
+    fn stack_effect(op: Op) -> isize {
+        match op {
+            Op::Constant(_)
+            | Op::Null
+            | Op::Dup
+            | Op::GetLocal(_)
+            | Op::GetUpvalue(_)
+            | Op::GetGlobal(_)
+            | Op::Closure(_)
+            | Op::ForNext { .. } => 1,
+            Op::Dup2 => 2,
+            Op::SetLocal(_)
+            | Op::SetUpvalue(_)
+            | Op::SetGlobal(_)
+            | Op::DefineGlobal(_)
+            | Op::CloseUpvalues(_)
+            | Op::Not
+            | Op::Neg
+            | Op::BitNot
+            | Op::Jump(_)
+            | Op::GetField(_)
+            | Op::CheckRange
+            | Op::IterValues
+            | Op::Return => 0,
+            Op::Array(n) => 1 - n as isize,
+            Op::Hash(n) => 1 - 2 * n as isize,
+            Op::Slice { start, end } => -(start as isize) - (end as isize),
+            Op::SetIndex => -2,
+            Op::Call(n) | Op::TailCall(n) => -(n as isize),
+            _ => -1,
+        }
+    }
+
+    // Point the jump at index to the next instruction
+    fn patch(&mut self, index: usize) {
+        let target = self.chunk().code.len() as u32;
+        match &mut self.chunk().code[index] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::ForNext { exit: to, .. } => *to = target,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    fn constant(&mut self, value: Object) -> Result<u16, InterpreterError> {
+        let key = ConstantKey::new(&value);
+        let state = self.state_mut();
+        let index = match state.constants.get(&key) {
+            Some(index) => *index,
+            None => {
+                let constants = &mut state.function.chunk.constants;
+                constants.push(value);
+                state.constants.insert(key, constants.len() - 1);
+                constants.len() - 1
+            }
+        };
+        u16::try_from(index).map_err(|_| self.limit_error("constants"))
+    }
+
+    fn name(&mut self, name: &str) -> Result<u16, InterpreterError> {
+        self.constant(Object::String(name.to_string()))
+    }
+
+    // Operand for the global numbered index
+    fn global(&self, index: usize) -> Result<u16, InterpreterError> {
+        u16::try_from(index).map_err(|_| self.limit_error("globals"))
+    }
+
+    fn begin_scope(&mut self) {
+        let state = self.state_mut();
+        let first_slot = state.next_slot;
+        state.scopes.push(Scope {
+            locals: vec![],
+            first_slot,
+            hidden: false,
+        });
+    }
+
+    fn begin_hidden_scope(&mut self) {
+        self.begin_scope();
+        self.state_mut().scopes.last_mut().expect("scope").hidden = true;
+    }
+
+    // Slots of the scope are reused by the next one, captured locals are
+    // closed first so closures keep their value
+    fn end_scope(&mut self) {
+        let scope = self.state_mut().scopes.pop().expect("scope");
+        if scope.locals.iter().any(|local| local.captured) {
+            self.emit(Op::CloseUpvalues(scope.first_slot));
+        }
+        self.state_mut().next_slot = scope.first_slot;
+    }
+
+    // Top level variables of the script are globals
+    fn is_global_scope(&self) -> bool {
+        self.state().script && self.state().scopes.is_empty()
+    }
+
+    fn declare(&mut self) -> Result<u16, InterpreterError> {
+        let slot = self.state().next_slot;
+        if slot == u16::MAX {
+            return Err(self.limit_error("local variables"));
+        }
+        let state = self.state_mut();
+        state.next_slot += 1;
+        state.function.slot_count = state.function.slot_count.max(state.next_slot as usize);
+        state.scopes.last_mut().expect("scope").locals.push(Local {
+            slot,
+            captured: false,
+        });
+        Ok(slot)
+    }
+
+    // Variable the resolver bound an identifier to. A local binding counts
+    // scopes out from the innermost one, through the functions the code is
+    // nested in, and a local of an enclosing function is captured
+    fn variable(&mut self, name: &str, binding: Binding) -> Result<Variable, InterpreterError> {
+        let (depth, index) = match binding {
+            Binding::Global(index) => return Ok(Variable::Global(self.global(index)?)),
+            Binding::Local { depth, slot } => (depth, slot),
+            Binding::Unresolved => unreachable!("unresolved identifier {name}"),
+        };
+        let (level, scope) = self
+            .states
+            .iter()
+            .enumerate()
+            .rev()
+            .flat_map(|(level, state)| {
+                let scopes = state.scopes.iter().enumerate().rev();
+                scopes
+                    .filter(|(_, scope)| !scope.hidden)
+                    .map(move |(scope, _)| (level, scope))
+            })
+            .nth(depth)
+            .expect("resolved scope");
+
+        let current = self.states.len() - 1;
+        let local = &mut self.states[level].scopes[scope].locals[index];
+        if level == current {
+            return Ok(Variable::Local(local.slot));
+        }
+        local.captured = true;
+        let slot = local.slot;
+        self.capture(current, level, slot).map(Variable::Upvalue)
+    }
+
+    // Capture slot of the function at owner in the function at level, each
+    // function in between captures it in turn
+    fn capture(&mut self, level: usize, owner: usize, slot: u16) -> Result<u16, InterpreterError> {
+        let capture = if level - 1 == owner {
+            Capture::Local(slot)
+        } else {
+            Capture::Upvalue(self.capture(level - 1, owner, slot)?)
+        };
+
+        let captures = &mut self.states[level].function.captures;
+        let index = match captures.iter().position(|c| *c == capture) {
+            Some(index) => index,
+            None => {
+                captures.push(capture);
+                captures.len() - 1
+            }
+        };
+        u16::try_from(index).map_err(|_| self.limit_error("captured variables"))
+    }
+
+    fn emit_get(&mut self, variable: &Variable) {
+        match *variable {
+            Variable::Local(slot) => self.emit(Op::GetLocal(slot)),
+            Variable::Upvalue(index) => self.emit(Op::GetUpvalue(index)),
+            Variable::Global(index) => self.emit(Op::GetGlobal(index)),
+        };
+    }
+
+    fn emit_set(&mut self, variable: &Variable) {
+        match *variable {
+            Variable::Local(slot) => self.emit(Op::SetLocal(slot)),
+            Variable::Upvalue(index) => self.emit(Op::SetUpvalue(index)),
+            Variable::Global(index) => self.emit(Op::SetGlobal(index)),
+        };
+    }
+
+    // Leave the value of the last statement on the stack, null for none.
+    // When tail is set that value is the result of the function
+    fn statements(
+        &mut self,
+        statements: &[Spanned<Statement>],
+        tail: bool,
+    ) -> Result<(), InterpreterError> {
+        if statements.is_empty() {
+            self.emit(Op::Null);
+        }
+        for (i, statement) in statements.iter().enumerate() {
+            let last = i == statements.len() - 1;
+            self.statement(statement, tail && last)?;
+            if !last {
+                let outer = mem::replace(&mut self.span, statement.span);
+                self.emit(Op::Pop);
+                self.span = outer;
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &[Spanned<Statement>], tail: bool) -> Result<(), InterpreterError> {
+        self.begin_scope();
+        self.statements(block, tail)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    // Every statement leaves its value on the stack
+    fn statement(
+        &mut self,
+        statement: &Spanned<Statement>,
+        tail: bool,
+    ) -> Result<(), InterpreterError> {
+        let outer = self.span;
+        self.span = statement.span;
+        self.statement_node(&statement.node, tail)?;
+        self.span = outer;
+        Ok(())
+    }
+
+    fn statement_node(
+        &mut self,
+        statement: &Statement,
+        tail: bool,
+    ) -> Result<(), InterpreterError> {
+        match statement {
+            Statement::Let(name, value) => self.let_statement(name, value),
+            Statement::Return(value) => {
+                match value {
+                    None => {
+                        self.emit(Op::Null);
+                    }
+                    Some(value) => self.tail_expression(value)?,
+                }
+                self.emit(Op::Return);
+                Ok(())
+            }
+            Statement::Expression(expr) if tail => self.tail_expression(expr),
+            Statement::Expression(expr) => self.expression(expr),
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                let start = self.chunk().code.len();
+                let depth = self.state().depth;
+                self.expression(condition)?;
+                let exit = self.emit(Op::JumpIfFalse(0));
+                let breaks = self.loop_body(label, start, depth, false, body)?;
+                self.patch(exit);
+                for index in breaks {
+                    self.patch(index);
+                }
+                self.emit(Op::Null);
+                Ok(())
+            }
+            Statement::For {
+                label,
+                iterable,
+                body,
+                ..
+            } => self.for_statement(label, iterable, body),
+            Statement::Break(label) => self.jump_out(label, true),
+            Statement::Continue(label) => self.jump_out(label, false),
+        }
+    }
+
+    fn let_statement(
+        &mut self,
+        name: &str,
+        value: &Option<Spanned<ExpressionStatement>>,
+    ) -> Result<(), InterpreterError> {
+        if self.is_global_scope() {
+            match value {
+                None => {
+                    self.emit(Op::Null);
+                }
+                Some(value) => self.expression(value)?,
+            }
+            let index = self.globals.index(name);
+            let index = self.global(index)?;
+            self.emit(Op::DefineGlobal(index));
+            return Ok(());
+        }
+
+        // A function literal can refer to the variable it is bound to, so
+        // local functions can be recursive. Any other value sees the outer
+        // variable of the same name, if there is one
+        let is_function =
+            matches!(value, Some(v) if matches!(v.node, ExpressionStatement::Fn { .. }));
+        let mut slot = None;
+        if is_function {
+            slot = Some(self.declare()?);
+        }
+        match value {
+            None => {
+                self.emit(Op::Null);
+            }
+            Some(value) => self.expression(value)?,
+        }
+        let slot = match slot {
+            Some(slot) => slot,
+            None => self.declare()?,
+        };
+        self.emit(Op::SetLocal(slot));
+        Ok(())
+    }
+
+    // The body of a loop gets a scope per iteration, holding the value on top
+    // of the stack when the loop has a variable. Returns the break jumps to patch to the loop's exit
+    fn loop_body(
+        &mut self,
+        label: &Option<String>,
+        start: usize,
+        depth: usize,
+        variable: bool,
+        body: &[Spanned<Statement>],
+    ) -> Result<Vec<usize>, InterpreterError> {
+        self.begin_scope();
+        let first_slot = self.state().next_slot;
+        self.state_mut().loops.push(Loop {
+            label: label.clone(),
+            start,
+            depth,
+            first_slot,
+            breaks: vec![],
+        });
+        if variable {
+            let slot = self.declare()?;
+            self.emit(Op::SetLocal(slot));
+            self.emit(Op::Pop);
+        }
+        for statement in body.iter() {
+            self.statement(statement, false)?;
+            self.emit(Op::Pop);
+        }
+        self.end_scope();
+        self.emit(Op::Jump(start as u32));
+        let lp = self.state_mut().loops.pop().expect("loop");
+        Ok(lp.breaks)
+    }
+
+    fn for_statement(
+        &mut self,
+        label: &Option<String>,
+        iterable: &Spanned<ExpressionStatement>,
+        body: &[Spanned<Statement>],
+    ) -> Result<(), InterpreterError> {
+        let span = self.span;
+        // Two hidden slots hold the loop's state: the values to visit and the
+        // index of the next one, or the end of a range and its next number
+        self.begin_hidden_scope();
+        let values = self.declare()?;
+        let counter = self.declare()?;
+        match &iterable.node {
+            // Ranges are counted through instead of being built as an array
+            ExpressionStatement::Range { start, end } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                self.span = iterable.span;
+                self.emit(Op::CheckRange);
+                self.emit(Op::SetLocal(values));
+                self.emit(Op::Pop);
+                self.emit(Op::SetLocal(counter));
+                self.emit(Op::Pop);
+            }
+            _ => {
+                self.expression(iterable)?;
+                self.span = iterable.span;
+                self.emit(Op::IterValues);
+                self.emit(Op::SetLocal(values));
+                self.emit(Op::Pop);
+                let zero = self.constant(Object::Integer(0))?;
+                self.emit(Op::Constant(zero));
+                self.emit(Op::SetLocal(counter));
+                self.emit(Op::Pop);
+            }
+        }
+        self.span = span;
+
+        let start = self.chunk().code.len();
+        let depth = self.state().depth;
+        let exit = self.emit(Op::ForNext {
+            slot: values,
+            exit: 0,
+        });
+        let breaks = self.loop_body(label, start, depth, true, body)?;
+        self.patch(exit);
+        for index in breaks {
+            self.patch(index);
+        }
+        self.end_scope();
+        self.emit(Op::Null);
+        Ok(())
+    }
+
+    // break or continue: drop the temporaries of the expressions being
+    // evaluated, close the loop body's captured locals and jump
+    fn jump_out(&mut self, label: &Option<String>, is_break: bool) -> Result<(), InterpreterError> {
+        let loops = &self.state().loops;
+        let index = match label {
+            None => loops.len().checked_sub(1),
+            Some(label) => loops
+                .iter()
+                .rposition(|lp| lp.label.as_ref() == Some(label)),
+        };
+        // The resolver rejects a jump out of no loop
+        let index = index.expect("enclosing loop");
+
+        let depth = self.state().depth;
+        let lp = &self.state().loops[index];
+        let (loop_depth, first_slot, start) = (lp.depth, lp.first_slot, lp.start);
+        for _ in loop_depth..depth {
+            self.emit(Op::Pop);
+        }
+        self.emit(Op::CloseUpvalues(first_slot));
+        if is_break {
+            let jump = self.emit(Op::Jump(0));
+            self.state_mut().loops[index].breaks.push(jump);
+        } else {
+            self.emit(Op::Jump(start as u32));
+        }
+        // Code after the jump is never run, the statement still counts as
+        // leaving a value
+        self.state_mut().depth = depth + 1;
+        Ok(())
+    }
+
+    // Compile an expression whose value is the result of the function, a call
+    // there replaces the running call instead of being made on top of it
+    fn tail_expression(
+        &mut self,
+        expr: &Spanned<ExpressionStatement>,
+    ) -> Result<(), InterpreterError> {
+        match &expr.node {
+            ExpressionStatement::Call { caller, args } => {
+                let outer = self.span;
+                self.span = expr.span;
+                let argc = self.call_operands(caller, args)?;
+                self.emit(Op::TailCall(argc));
+                self.span = outer;
+                Ok(())
+            }
+            ExpressionStatement::If {
+                condition,
+                outcome,
+                alternate,
+            } => {
+                let outer = self.span;
+                self.span = expr.span;
+                self.if_expression(condition, outcome, alternate, true)?;
+                self.span = outer;
+                Ok(())
+            }
+            ExpressionStatement::Group(inner) => self.tail_expression(inner),
+            _ => self.expression(expr),
+        }
+    }
+
+    fn expression(&mut self, expr: &Spanned<ExpressionStatement>) -> Result<(), InterpreterError> {
+        let outer = self.span;
+        self.span = expr.span;
+        self.expression_node(&expr.node)?;
+        self.span = outer;
+        Ok(())
+    }
+
+    fn expression_node(&mut self, expr: &ExpressionStatement) -> Result<(), InterpreterError> {
+        match expr {
+            ExpressionStatement::Prefix { operator, right } => {
+                self.expression(right)?;
+                let op = match operator {
+                    Token::Bang => Op::Not,
+                    Token::Minus => Op::Neg,
+                    Token::Tilde => Op::BitNot,
+                    _ => {
+                        return Err(
+                            self.runtime_error(RuntimeError::UnknownOperator(operator.clone()))
+                        )
+                    }
+                };
+                self.emit(op);
+            }
+            ExpressionStatement::Infix {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.binary(operator)?;
+            }
+            ExpressionStatement::If {
+                condition,
+                outcome,
+                alternate,
+            } => self.if_expression(condition, outcome, alternate, false)?,
+            ExpressionStatement::Fn { args, body } => self.function(args, body)?,
+            ExpressionStatement::Call { caller, args } => {
+                let argc = self.call_operands(caller, args)?;
+                self.emit(Op::Call(argc));
+            }
+            ExpressionStatement::Index { left, index } => {
+                self.expression(left)?;
+                self.expression(index)?;
+                self.emit(Op::Index);
+            }
+            ExpressionStatement::Slice { left, start, end } => {
+                self.expression(left)?;
+                if let Some(start) = start {
+                    self.expression(start)?;
+                }
+                if let Some(end) = end {
+                    self.expression(end)?;
+                }
+                self.emit(Op::Slice {
+                    start: start.is_some(),
+                    end: end.is_some(),
+                });
+            }
+            ExpressionStatement::Field { left, name } => {
+                self.expression(left)?;
+                let name = self.name(name)?;
+                self.emit(Op::GetField(name));
+            }
+            ExpressionStatement::Assign {
+                target,
+                operator,
+                value,
+            } => self.assign(target, operator, value)?,
+            ExpressionStatement::Range { start, end } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                self.emit(Op::Range);
+            }
+            ExpressionStatement::Group(expr) => self.expression(expr)?,
+            ExpressionStatement::Array(elements) => {
+                for element in elements.iter() {
+                    self.expression(element)?;
+                }
+                let n = u16::try_from(elements.len()).map_err(|_| self.limit_error("elements"))?;
+                self.emit(Op::Array(n));
+            }
+            ExpressionStatement::Hash(pairs) => {
+                for (key, value) in pairs.iter() {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                let n = u16::try_from(pairs.len()).map_err(|_| self.limit_error("elements"))?;
+                self.emit(Op::Hash(n));
+            }
+            ExpressionStatement::Identifier(name, binding) => {
+                let variable = self.variable(name, *binding)?;
+                self.emit_get(&variable);
+            }
+            ExpressionStatement::Int(num) => self.emit_constant(Object::Integer(*num))?,
+            ExpressionStatement::Num(num) => self.emit_constant(Object::Number(*num))?,
+            ExpressionStatement::Str(s) => self.emit_constant(Object::String(s.clone()))?,
+            ExpressionStatement::Bool(b) => self.emit_constant(Object::Boolean(*b))?,
+        }
+        Ok(())
+    }
+
+    fn emit_constant(&mut self, value: Object) -> Result<(), InterpreterError> {
+        let index = self.constant(value)?;
+        self.emit(Op::Constant(index));
+        Ok(())
+    }
+
+    fn binary(&mut self, operator: &Token) -> Result<(), InterpreterError> {
+        let op = match operator {
+            Token::Plus => Op::Add,
+            Token::Minus => Op::Sub,
+            Token::Asterisk => Op::Mul,
+            Token::Slash => Op::Div,
+            Token::Percent => Op::Mod,
+            Token::DoubleAsterisk => Op::Pow,
+            Token::DoubleSlash => Op::FloorDiv,
+            Token::Ampersand => Op::BitAnd,
+            Token::Pipe => Op::BitOr,
+            Token::Caret => Op::BitXor,
+            Token::ShiftLeft => Op::Shl,
+            Token::ShiftRight => Op::Shr,
+            Token::Equal => Op::Equal,
+            Token::NotEqual => Op::NotEqual,
+            Token::Gt => Op::Gt,
+            Token::Gte => Op::Gte,
+            Token::Lt => Op::Lt,
+            Token::Lte => Op::Lte,
+            _ => return Err(self.runtime_error(RuntimeError::UnknownOperator(operator.clone()))),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn if_expression(
+        &mut self,
+        condition: &Spanned<ExpressionStatement>,
+        outcome: &[Spanned<Statement>],
+        alternate: &[Spanned<Statement>],
+        tail: bool,
+    ) -> Result<(), InterpreterError> {
+        self.expression(condition)?;
+        let else_jump = self.emit(Op::JumpIfFalse(0));
+        self.block(outcome, tail)?;
+        let end_jump = self.emit(Op::Jump(0));
+        // Only one of the branches leaves its value
+        self.state_mut().depth -= 1;
+        self.patch(else_jump);
+        self.block(alternate, tail)?;
+        self.patch(end_jump);
+        Ok(())
+    }
+
+    // Push the callee and its arguments, returns the argument count
+    fn call_operands(
+        &mut self,
+        caller: &Spanned<ExpressionStatement>,
+        args: &[Spanned<ExpressionStatement>],
+    ) -> Result<u8, InterpreterError> {
+        let argc = u8::try_from(args.len()).map_err(|_| self.limit_error("arguments"))?;
+        self.expression(caller)?;
+        for arg in args.iter() {
+            self.expression(arg)?;
+        }
+        Ok(argc)
+    }
+
+    // Parameters take the first slots and share the body's scope, as they
+    // share the evaluator's function environment
+    fn function(
+        &mut self,
+        params: &[String],
+        body: &[Spanned<Statement>],
+    ) -> Result<(), InterpreterError> {
+        let name = format!("fn({})", params.join(", "));
+        self.states
+            .push(FunctionState::new(name, params.to_vec(), false));
+        self.begin_scope();
+        for _ in params.iter() {
+            self.declare()?;
+        }
+        self.statements(body, true)?;
+        self.emit(Op::Return);
+
+        let state = self.states.pop().expect("function state");
+        let chunk = self.chunk();
+        chunk.functions.push(Rc::new(state.function));
+        let index =
+            u16::try_from(chunk.functions.len() - 1).map_err(|_| self.limit_error("functions"))?;
+        self.emit(Op::Closure(index));
+        Ok(())
+    }
+
+    fn assign(
+        &mut self,
+        target: &Spanned<ExpressionStatement>,
+        operator: &Option<Token>,
+        value: &Spanned<ExpressionStatement>,
+    ) -> Result<(), InterpreterError> {
+        match &target.node {
+            ExpressionStatement::Identifier(name, binding) => {
+                let variable = self.variable(name, *binding)?;
+                // For compound assignment the current value is read first
+                if operator.is_some() {
+                    self.emit_get(&variable);
+                }
+                self.expression(value)?;
+                if let Some(operator) = operator {
+                    self.binary(operator)?;
+                }
+                self.emit_set(&variable);
+            }
+            ExpressionStatement::Index { left, index } => {
+                self.expression(left)?;
+                self.expression(index)?;
+                if operator.is_some() {
+                    self.emit(Op::Dup2);
+                    self.emit(Op::Index);
+                }
+                self.expression(value)?;
+                if let Some(operator) = operator {
+                    self.binary(operator)?;
+                }
+                self.emit(Op::SetIndex);
+            }
+            ExpressionStatement::Field { left, name } => {
+                self.expression(left)?;
+                let name = self.name(name)?;
+                if operator.is_some() {
+                    self.emit(Op::Dup);
+                    self.emit(Op::GetField(name));
+                }
+                self.expression(value)?;
+                if let Some(operator) = operator {
+                    self.binary(operator)?;
+                }
+                self.emit(Op::SetField(name));
+            }
+            _ => {
+                return Err(
+                    self.runtime_error(RuntimeError::InvalidAssignmentTarget(target.node.clone()))
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+// Listing of a function and every function nested in it, one instruction per
+// line with its offset and source position. Globals are named from the
+// table the function was compiled with
+pub fn disassemble(function: &CompiledFunction, globals: &Globals) -> String {
+    let mut out = String::new();
+    write_function(&mut out, function, globals);
+    out
+}
+
+fn write_function(out: &mut String, function: &CompiledFunction, globals: &Globals) {
+    let chunk = &function.chunk;
+    writeln!(out, "== {} ==", function.name).unwrap();
+    for (offset, (op, span)) in chunk.code.iter().zip(chunk.spans.iter()).enumerate() {
+        let position = format!("{}:{}", span.line, span.column);
+        write!(out, "{:04} {:>7}  {:?}", offset, position, op).unwrap();
+        match *op {
+            Op::Constant(index) | Op::GetField(index) | Op::SetField(index) => {
+                write!(out, "  ; {}", chunk.constants[index as usize]).unwrap()
+            }
+            Op::GetGlobal(index) | Op::SetGlobal(index) | Op::DefineGlobal(index) => {
+                write!(out, "  ; {}", globals.name(index as usize)).unwrap()
+            }
+            Op::Closure(index) => {
+                write!(out, "  ; {}", chunk.functions[index as usize].name).unwrap()
+            }
+            _ => {}
+        }
+        out.push('\n');
+    }
+    for nested in chunk.functions.iter() {
+        out.push('\n');
+        write_function(out, nested, globals);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::Environment;
+    use crate::{lexer, parser, resolver};
+
+    fn compile_in(
+        input: &str,
+        env: &mut Environment,
+    ) -> Result<Rc<CompiledFunction>, InterpreterError> {
+        let (mut program, errors) = parser::Parser::new(lexer::Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "{input}: {:?}", errors);
+        resolver::resolve(&mut program, env)?;
+        compile(&program, &mut env.variables)
+    }
+
+    fn compile_str(input: &str) -> Result<Rc<CompiledFunction>, InterpreterError> {
+        compile_in(input, &mut Environment::new(None).borrow_mut())
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let env = Environment::new(None);
+        let mut env = env.borrow_mut();
+        env.variables.insert("defined".to_string(), Object::Null);
+        let script = compile_in("let a = 1;\nlet f = fn(x) { x + a };\nf(2)", &mut env).unwrap();
+        assert_eq!(
+            disassemble(&script, &env.variables),
+            "== <script> ==
+0000     1:9  Constant(0)  ; 1
+0001     1:1  DefineGlobal(1)  ; a
+0002     1:1  Pop
+0003     2:9  Closure(0)  ; fn(x)
+0004     2:1  DefineGlobal(2)  ; f
+0005     2:1  Pop
+0006     3:1  GetGlobal(2)  ; f
+0007     3:3  Constant(1)  ; 2
+0008     3:1  Call(1)
+0009     3:1  Return
+
+== fn(x) ==
+0000    2:17  GetLocal(0)
+0001    2:21  GetGlobal(1)  ; a
+0002    2:17  Add
+0003     2:9  Return
+"
+        );
+    }
+
+    #[test]
+    fn test_compile_loop() {
+        let script = compile_str("for i in 0..3 { if i == 1 { continue }; i }").unwrap();
+        let code = &script.chunk.code;
+        let exit = match code.iter().find(|op| matches!(op, Op::ForNext { .. })) {
+            Some(Op::ForNext { slot: 0, exit }) => *exit as usize,
+            op => panic!("Expected ForNext, got {:?}", op),
+        };
+        // The loop exits to the null it leaves as its value
+        assert_eq!(&code[exit..], &[Op::Null, Op::Return]);
+        assert_eq!(script.slot_count, 3);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let testcases = vec![
+            ("return 1", RuntimeError::ReturnOutsideFunction),
+            (
+                "if true { let a = 1; let a = 2 }",
+                RuntimeError::AlreadyDeclared("a".to_string()),
+            ),
+            ("fn() { break }", RuntimeError::OutsideLoop("break")),
+            (
+                "while true { continue inner }",
+                RuntimeError::UndefinedLabel("inner".to_string()),
+            ),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            match compile_str(input) {
+                Err(InterpreterError::Syntax(errors)) => match &errors[..] {
+                    [InterpreterError::Resolve(e, _)] => assert_eq!(*e, expected, "{input}"),
+                    errors => panic!("Expected one error for {input}, got {:?}", errors),
+                },
+                result => panic!("Expected error for {input}, got {:?}", result.map(|_| ())),
+            }
+        }
+
+        let limits = [
+            (
+                (0..70000).map(|i| format!("{i};")).collect::<String>(),
+                "constants",
+            ),
+            (
+                (0..70000).map(|i| format!("let v{i} = 0;")).collect(),
+                "globals",
+            ),
+        ];
+        for (input, what) in limits {
+            match compile_str(&input) {
+                Err(InterpreterError::Compile(CompileError::LimitExceeded(e), _)) => {
+                    assert_eq!(e, what)
+                }
+                result => panic!("Expected too many {what}, got {:?}", result.map(|_| ())),
+            }
+        }
+    }
+}
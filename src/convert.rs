@@ -0,0 +1,237 @@
+// Conversions between Rust values and objects, used by host functions to read
+// their arguments and build their results
+use crate::error::RuntimeError;
+use crate::object::{Hash, Object};
+
+use std::collections::HashMap;
+
+fn mismatch(expected: &'static str, found: &Object) -> RuntimeError {
+    RuntimeError::Conversion {
+        expected,
+        found: found.clone(),
+    }
+}
+
+// Integers are widened, so a host function taking f64 accepts both
+impl TryFrom<&Object> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(obj: &Object) -> Result<Self, RuntimeError> {
+        match obj {
+            Object::Integer(num) => Ok(*num as f64),
+            Object::Number(num) => Ok(*num),
+            _ => Err(mismatch("a number", obj)),
+        }
+    }
+}
+
+impl TryFrom<&Object> for i64 {
+    type Error = RuntimeError;
+
+    fn try_from(obj: &Object) -> Result<Self, RuntimeError> {
+        match obj {
+            Object::Integer(num) => Ok(*num),
+            _ => Err(mismatch("an integer", obj)),
+        }
+    }
+}
+
+impl TryFrom<&Object> for String {
+    type Error = RuntimeError;
+
+    fn try_from(obj: &Object) -> Result<Self, RuntimeError> {
+        match obj {
+            Object::String(s) => Ok(s.clone()),
+            _ => Err(mismatch("a string", obj)),
+        }
+    }
+}
+
+impl TryFrom<&Object> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(obj: &Object) -> Result<Self, RuntimeError> {
+        match obj {
+            Object::Boolean(b) => Ok(*b),
+            _ => Err(mismatch("a boolean", obj)),
+        }
+    }
+}
+
+impl<T> TryFrom<&Object> for Vec<T>
+where
+    T: for<'a> TryFrom<&'a Object, Error = RuntimeError>,
+{
+    type Error = RuntimeError;
+
+    fn try_from(obj: &Object) -> Result<Self, RuntimeError> {
+        match obj {
+            Object::Array(elements) => elements.borrow().iter().map(T::try_from).collect(),
+            _ => Err(mismatch("an array", obj)),
+        }
+    }
+}
+
+// Only hashes whose keys are all strings can be converted
+impl<V> TryFrom<&Object> for HashMap<String, V>
+where
+    V: for<'a> TryFrom<&'a Object, Error = RuntimeError>,
+{
+    type Error = RuntimeError;
+
+    fn try_from(obj: &Object) -> Result<Self, RuntimeError> {
+        match obj {
+            Object::Hash(hash) => hash
+                .borrow()
+                .iter()
+                .map(|pair| Ok((String::try_from(&pair.key)?, V::try_from(&pair.value)?)))
+                .collect(),
+            _ => Err(mismatch("a hash", obj)),
+        }
+    }
+}
+
+// Argument index of a host function, converted to T. Fails with ArgumentCount
+// when the function was called with fewer arguments, instead of panicking
+pub fn arg<'a, T>(args: &'a [Object], index: usize) -> Result<T, RuntimeError>
+where
+    T: TryFrom<&'a Object, Error = RuntimeError>,
+{
+    match args.get(index) {
+        Some(arg) => T::try_from(arg),
+        None => Err(RuntimeError::ArgumentCount {
+            expected: index + 1,
+            found: args.len(),
+        }),
+    }
+}
+
+impl From<f64> for Object {
+    fn from(num: f64) -> Self {
+        Object::Number(num)
+    }
+}
+
+impl From<i64> for Object {
+    fn from(num: i64) -> Self {
+        Object::Integer(num)
+    }
+}
+
+impl From<String> for Object {
+    fn from(s: String) -> Self {
+        Object::String(s)
+    }
+}
+
+impl From<&str> for Object {
+    fn from(s: &str) -> Self {
+        Object::String(s.to_string())
+    }
+}
+
+impl From<bool> for Object {
+    fn from(b: bool) -> Self {
+        Object::Boolean(b)
+    }
+}
+
+impl From<()> for Object {
+    fn from(_: ()) -> Self {
+        Object::Null
+    }
+}
+
+impl<T: Into<Object>> From<Vec<T>> for Object {
+    fn from(elements: Vec<T>) -> Self {
+        Object::new_array(elements.into_iter().map(Into::into).collect())
+    }
+}
+
+// HashMap iteration order is unspecified, so pairs are inserted sorted by key
+// to keep printing and iterating the hash deterministic
+impl<V: Into<Object>> From<HashMap<String, V>> for Object {
+    fn from(map: HashMap<String, V>) -> Self {
+        let mut pairs: Vec<(String, V)> = map.into_iter().collect();
+        pairs.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+
+        let mut hash = Hash::new();
+        for (key, value) in pairs.into_iter() {
+            hash.insert(Object::String(key), value.into())
+                .expect("string keys are hashable");
+        }
+        Object::new_hash(hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_object() {
+        assert_eq!(f64::try_from(&Object::Integer(2)), Ok(2.0));
+        assert_eq!(f64::try_from(&Object::Number(2.5)), Ok(2.5));
+        assert_eq!(i64::try_from(&Object::Integer(-3)), Ok(-3));
+        assert_eq!(
+            i64::try_from(&Object::Number(1.5)),
+            Err(RuntimeError::Conversion {
+                expected: "an integer",
+                found: Object::Number(1.5),
+            })
+        );
+        assert_eq!(String::try_from(&Object::from("a")), Ok("a".to_string()));
+        assert_eq!(bool::try_from(&Object::Boolean(true)), Ok(true));
+        assert_eq!(
+            Vec::<i64>::try_from(&Object::from(vec![1_i64, 2])),
+            Ok(vec![1, 2])
+        );
+        assert!(Vec::<i64>::try_from(&Object::from(vec![1.5])).is_err());
+
+        let map = HashMap::from([("b".to_string(), 2_i64), ("a".to_string(), 1)]);
+        assert_eq!(
+            HashMap::<String, i64>::try_from(&Object::from(map.clone())),
+            Ok(map)
+        );
+    }
+
+    #[test]
+    fn test_arg() {
+        let args = [Object::Integer(1), Object::from("a")];
+        assert_eq!(arg::<i64>(&args, 0), Ok(1));
+        assert_eq!(arg::<String>(&args, 1), Ok("a".to_string()));
+        assert_eq!(
+            arg::<i64>(&args, 1),
+            Err(RuntimeError::Conversion {
+                expected: "an integer",
+                found: Object::from("a"),
+            })
+        );
+        assert_eq!(
+            arg::<bool>(&args, 2),
+            Err(RuntimeError::ArgumentCount {
+                expected: 3,
+                found: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_into_object() {
+        assert_eq!(Object::from(1.5), Object::Number(1.5));
+        assert_eq!(Object::from(1_i64), Object::Integer(1));
+        assert_eq!(
+            Object::from("a".to_string()),
+            Object::String("a".to_string())
+        );
+        assert_eq!(Object::from(false), Object::Boolean(false));
+        assert_eq!(Object::from(()), Object::Null);
+        assert_eq!(
+            Object::from(vec![vec![true]]),
+            Object::new_array(vec![Object::new_array(vec![Object::Boolean(true)])])
+        );
+
+        let map = HashMap::from([("b".to_string(), 2_i64), ("a".to_string(), 1)]);
+        assert_eq!(Object::from(map).to_string(), "{a: 1, b: 2}");
+    }
+}
@@ -0,0 +1,334 @@
+use crate::object::Object;
+use crate::span::Span;
+use crate::statement::ExpressionStatement;
+use crate::token::Token;
+
+use std::error;
+use std::fmt;
+
+// Malformed token, carried by Token::Illegal until the parser reports it
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    IllegalCharacter(char),
+    InvalidNumber { literal: String, reason: String },
+    UnterminatedString,
+    UnterminatedRawString,
+    UnknownEscape(char),
+    MissingUnicodeBrace,
+    InvalidUnicodeCharacter(char),
+    InvalidUnicodeEscape(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken { expected: String, found: Token },
+    InvalidAssignmentTarget(ExpressionStatement),
+}
+
+// Program the bytecode compiler can not encode
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    // What there are too many of in one function, e.g. "constants"
+    LimitExceeded(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable(String),
+    UndeclaredAssignment(String),
+    AlreadyDeclared(String),
+    ReturnOutsideFunction,
+    // break or continue, by keyword, that is not inside a loop
+    OutsideLoop(&'static str),
+    UndefinedLabel(String),
+    NotIterable(Object),
+    UnknownOperator(Token),
+    InvalidAssignmentTarget(ExpressionStatement),
+    // Binary operator applied to operands it does not support
+    TypeMismatch {
+        operator: &'static str,
+        left: Object,
+        right: Object,
+    },
+    // Unary operator applied to an operand it does not support
+    InvalidOperand {
+        operator: &'static str,
+        value: Object,
+    },
+    NotComparable(Object, Object),
+    NotCallable(Object),
+    CallDepthExceeded(usize),
+    ArgumentCount {
+        expected: usize,
+        found: usize,
+    },
+    // Builtin called with an argument of a type it does not accept
+    InvalidArgument {
+        function: String,
+        expected: &'static str,
+        found: Object,
+    },
+    // Argument of a host function that can not be converted to the Rust type
+    // it expects
+    Conversion {
+        expected: &'static str,
+        found: Object,
+    },
+    // Error returned by a host function
+    Host(String),
+    NotIndexable(Object),
+    NoFields(Object),
+    NotIndexAssignable(Object),
+    NotSliceable(Object),
+    IndexOutOfRange {
+        index: i64,
+        length: usize,
+    },
+    InvalidIndex(Object),
+    UnhashableKey(Object),
+    NanHashKey,
+    DivisionByZero,
+    IntegerOverflow(String),
+    ShiftOutOfRange(i64),
+}
+
+// Any error raised while running source code. Lex and parse errors always point
+// at a token, runtime errors point at the innermost AST node they were raised
+// from once the evaluator attaches it
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterpreterError {
+    Lex(LexError, Span),
+    Parse(ParseError, Span),
+    Compile(CompileError, Span),
+    Runtime(RuntimeError, Option<Span>),
+    // Error the resolver finds before the program runs, one the evaluator
+    // would only raise on reaching the code
+    Resolve(RuntimeError, Span),
+    // Every lex and parse error found in a source that failed to parse, or
+    // every resolve error of a program that failed to resolve
+    Syntax(Vec<InterpreterError>),
+    // Source file that could not be read
+    Io { path: String, message: String },
+}
+
+impl InterpreterError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Lex(_, span)
+            | Self::Parse(_, span)
+            | Self::Compile(_, span)
+            | Self::Resolve(_, span) => Some(*span),
+            Self::Runtime(_, span) => *span,
+            Self::Syntax(errors) => errors.first().and_then(|e| e.span()),
+            Self::Io { .. } => None,
+        }
+    }
+
+    // Attach span unless a more precise location was already recorded
+    pub fn or_span(self, span: Span) -> Self {
+        match self {
+            Self::Runtime(e, None) => Self::Runtime(e, Some(span)),
+            e => e,
+        }
+    }
+
+    // Message followed by the location and offending source line
+    pub fn render(&self, source: &str) -> String {
+        if let Self::Syntax(errors) = self {
+            let errors: Vec<String> = errors.iter().map(|e| e.render(source)).collect();
+            return errors.join("\n");
+        }
+
+        match self.span() {
+            None => self.to_string(),
+            Some(span) => format!(
+                "{} at line {}, column {}\n{}",
+                self,
+                span.line,
+                span.column,
+                span.render(source)
+            ),
+        }
+    }
+}
+
+impl From<RuntimeError> for InterpreterError {
+    fn from(e: RuntimeError) -> Self {
+        Self::Runtime(e, None)
+    }
+}
+
+// Lets host functions fail with a plain message
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        Self::Host(message)
+    }
+}
+
+impl From<&str> for RuntimeError {
+    fn from(message: &str) -> Self {
+        Self::Host(message.to_string())
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalCharacter(c) => write!(f, "Illegal character {:?}", c),
+            Self::InvalidNumber { literal, reason } => {
+                write!(f, "Invalid number {}, err = {}", literal, reason)
+            }
+            Self::UnterminatedString => write!(f, "Unterminated string literal"),
+            Self::UnterminatedRawString => write!(f, "Unterminated raw string literal"),
+            Self::UnknownEscape(c) => write!(f, "Unknown escape sequence \\{}", c),
+            Self::MissingUnicodeBrace => write!(f, "Expected '{{' after \\u in unicode escape"),
+            Self::InvalidUnicodeCharacter(c) => {
+                write!(f, "Invalid character {:?} in unicode escape", c)
+            }
+            Self::InvalidUnicodeEscape(hex) => write!(f, "Invalid unicode escape \\u{{{}}}", hex),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedToken { expected, found } => {
+                write!(f, "Expected {}, got {:?}", expected, found)
+            }
+            Self::InvalidAssignmentTarget(target) => {
+                write!(f, "Invalid assignment target {:?}", target)
+            }
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LimitExceeded(what) => write!(f, "Too many {} in one function", what),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVariable(name) => write!(f, "undefined variable {:?}", name),
+            Self::UndeclaredAssignment(name) => {
+                write!(f, "Can not assign to undeclared variable {:?}", name)
+            }
+            Self::AlreadyDeclared(name) => write!(f, "{} is initialized", name),
+            Self::ReturnOutsideFunction => write!(f, "'return' outside function"),
+            Self::OutsideLoop(keyword) => write!(f, "'{}' outside loop", keyword),
+            Self::UndefinedLabel(label) => write!(f, "undefined loop label {:?}", label),
+            Self::NotIterable(v) => write!(f, "{} is not iterable", v.repr()),
+            Self::UnknownOperator(operator) => write!(f, "Unknown operator {:?}", operator),
+            Self::InvalidAssignmentTarget(target) => {
+                write!(f, "Invalid assignment target {:?}", target)
+            }
+            Self::TypeMismatch {
+                operator,
+                left,
+                right,
+            } => write!(
+                f,
+                "Unsupported operand types for {}: {} and {}",
+                operator,
+                left.repr(),
+                right.repr()
+            ),
+            Self::InvalidOperand { operator, value } => {
+                write!(
+                    f,
+                    "Unsupported operand type for unary {}: {}",
+                    operator,
+                    value.repr()
+                )
+            }
+            Self::NotComparable(left, right) => {
+                write!(f, "Can not compare {} with {}", left.repr(), right.repr())
+            }
+            Self::NotCallable(v) => write!(f, "{} is not a function", v.repr()),
+            Self::CallDepthExceeded(depth) => {
+                write!(f, "Maximum call depth of {} exceeded", depth)
+            }
+            Self::ArgumentCount { expected, found } => {
+                write!(f, "Expected {} arguments, got {}", expected, found)
+            }
+            Self::InvalidArgument {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} expected {}, got {}",
+                function,
+                expected,
+                found.repr()
+            ),
+            Self::Conversion { expected, found } => {
+                write!(f, "Expected {}, got {}", expected, found.repr())
+            }
+            Self::Host(message) => write!(f, "{}", message),
+            Self::NotIndexable(v) => write!(f, "{} is not indexable", v.repr()),
+            Self::NoFields(v) => write!(f, "{} has no fields", v.repr()),
+            Self::NotIndexAssignable(v) => {
+                write!(f, "{} does not support index assignment", v.repr())
+            }
+            Self::NotSliceable(v) => write!(f, "{} can not be sliced", v.repr()),
+            Self::IndexOutOfRange { index, length } => {
+                write!(f, "Index {} out of range for length {}", index, length)
+            }
+            Self::InvalidIndex(v) => write!(f, "Index must be an integer, got {}", v.repr()),
+            Self::UnhashableKey(v) => write!(
+                f,
+                "Unhashable key {}, only numbers, strings and booleans can be hash keys",
+                v.repr()
+            ),
+            Self::NanHashKey => write!(f, "NaN can not be used as a hash key"),
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::IntegerOverflow(expression) => write!(f, "Integer overflow in {}", expression),
+            Self::ShiftOutOfRange(amount) => {
+                write!(f, "Shift amount {} out of range 0..{}", amount, i64::BITS)
+            }
+        }
+    }
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lex(e, _) => write!(f, "{}", e),
+            Self::Parse(e, _) => write!(f, "{}", e),
+            Self::Compile(e, _) => write!(f, "{}", e),
+            Self::Runtime(e, _) | Self::Resolve(e, _) => write!(f, "{}", e),
+            Self::Syntax(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            Self::Io { path, message } => write!(f, "Can not read {}: {}", path, message),
+        }
+    }
+}
+
+impl error::Error for LexError {}
+
+impl error::Error for ParseError {}
+
+impl error::Error for CompileError {}
+
+impl error::Error for RuntimeError {}
+
+impl error::Error for InterpreterError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Lex(e, _) => Some(e),
+            Self::Parse(e, _) => Some(e),
+            Self::Compile(e, _) => Some(e),
+            Self::Runtime(e, _) | Self::Resolve(e, _) => Some(e),
+            Self::Syntax(errors) => errors.first().map(|e| e as &(dyn error::Error + 'static)),
+            Self::Io { .. } => None,
+        }
+    }
+}
@@ -1,231 +1,706 @@
-use crate::object::{Environment, Object};
+use crate::error::{InterpreterError, RuntimeError};
+use crate::object::{self, Environment, Function, Object};
 use crate::parser::Program;
+use crate::resolver;
+use crate::span::{Span, Spanned};
 use crate::statement::{
+    Binding,
     ExpressionStatement::{self, *},
     Statement::{self, *},
 };
 use crate::token::Token;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::rc::Rc;
 
-pub struct Evaluator {}
+// How evaluating a statement ended. Anything but Normal unwinds to the
+// construct that handles it: the function call for Return, the matching loop
+// for Break and Continue, and the caller of the evaluator for Throw
+enum ControlFlow {
+    Normal(Object),
+    Return(Object),
+    Break(Option<String>),
+    Continue(Option<String>),
+    // Boxed to keep the Result every expression returns small
+    Throw(Box<InterpreterError>),
+    // Call in tail position, made by the call it unwinds to in place of the
+    // function that returns it
+    TailCall(Rc<Function>, Vec<Object>),
+}
+
+impl ControlFlow {
+    // Expressions evaluate to a value, any other flow passes through them as
+    // an Err so that ? keeps unwinding it
+    fn into_value(self) -> Result<Object, ControlFlow> {
+        match self {
+            Self::Normal(v) => Ok(v),
+            flow => Err(flow),
+        }
+    }
+
+    fn or_span(self, span: Span) -> Self {
+        match self {
+            Self::Throw(e) => Self::Throw(Box::new(e.or_span(span))),
+            flow => flow,
+        }
+    }
+}
+
+impl From<Result<Object, ControlFlow>> for ControlFlow {
+    fn from(result: Result<Object, ControlFlow>) -> Self {
+        match result {
+            Ok(v) => Self::Normal(v),
+            Err(flow) => flow,
+        }
+    }
+}
+
+impl From<InterpreterError> for ControlFlow {
+    fn from(e: InterpreterError) -> Self {
+        Self::Throw(Box::new(e))
+    }
+}
+
+impl From<RuntimeError> for ControlFlow {
+    fn from(e: RuntimeError) -> Self {
+        Self::Throw(Box::new(e.into()))
+    }
+}
+
+// Calls nested deeper than this raise an error instead of overflowing the
+// native stack. Every script call takes several Rust frames, at this depth the
+// evaluating thread needs a few MB of stack, and tens of MB in debug builds
+pub(crate) const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+pub struct Evaluator {
+    max_call_depth: usize,
+    call_depth: Cell<usize>,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Evaluator {
     pub fn new() -> Self {
-        return Self {};
+        Self {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            call_depth: Cell::new(0),
+        }
     }
 
+    // Tail calls do not count towards the depth
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    // The program is resolved against the environment first, see
+    // resolver::resolve
     pub fn eval(
         &self,
-        program: Program,
+        mut program: Program,
         environment: Rc<RefCell<Environment>>,
-    ) -> Result<Object, String> {
+    ) -> Result<Object, InterpreterError> {
+        resolver::resolve(&mut program, &mut environment.borrow_mut())?;
         let mut last_v = Object::Null;
         for statement in program.statements.iter() {
-            match statement {
-                Let(variable_name, value) => {
-                    if environment.borrow().variables.contains_key(variable_name) {
-                        return Err(format!("{variable_name} is initialized"));
-                    }
-
-                    let mut v = match value {
-                        None => Object::Null,
-                        Some(expr) => {
-                            let v = self.eval_expression(environment.clone(), &expr);
-                            match v {
-                                Err(e) => return Err(e),
-                                Ok(v) => v,
-                            }
-                        }
-                    };
-                    last_v = v.clone();
-                    environment
-                        .borrow_mut()
-                        .variables
-                        .insert(variable_name.clone(), v);
+            last_v = match self.eval_statement(statement, environment.clone(), false) {
+                ControlFlow::Normal(v) => v,
+                ControlFlow::Throw(e) => return Err(*e),
+                flow => {
+                    return Err(InterpreterError::Runtime(
+                        Self::escaped(flow),
+                        Some(statement.span),
+                    ))
                 }
-                Return(_) => {
-                    return Err("'return' outside function".to_string());
-                }
-                Expression(expr) => match self.eval_expression(environment.clone(), &expr) {
-                    Ok(obj) => {
-                        match obj {
-                            Object::Return(_) => {
-                                return Err("'return' outside function".to_string())
-                            }
-                            _ => {}
-                        }
-                        last_v = obj;
-                    }
-                    Err(e) => return Err(e),
-                },
+            };
+        }
+        Ok(last_v)
+    }
+
+    // Error for a return, break or continue that unwound to a function body
+    // or the top level without meeting the construct it is meant for
+    fn escaped(flow: ControlFlow) -> RuntimeError {
+        match flow {
+            ControlFlow::Return(_) | ControlFlow::TailCall(..) => {
+                RuntimeError::ReturnOutsideFunction
             }
+            ControlFlow::Break(Some(label)) | ControlFlow::Continue(Some(label)) => {
+                RuntimeError::UndefinedLabel(label)
+            }
+            ControlFlow::Break(None) => RuntimeError::OutsideLoop("break"),
+            ControlFlow::Continue(None) => RuntimeError::OutsideLoop("continue"),
+            ControlFlow::Normal(_) | ControlFlow::Throw(_) => unreachable!(),
         }
-        return Ok(last_v);
     }
 
-    pub fn eval_block(
+    // The block stops at the first statement that does not end normally. When
+    // tail is set the value of the block is the result of the function, so its
+    // last statement is in tail position
+    fn eval_block(
         &self,
-        block: &Vec<Statement>,
+        block: &[Spanned<Statement>],
         environment: Rc<RefCell<Environment>>,
-    ) -> Result<Object, String> {
+        tail: bool,
+    ) -> ControlFlow {
         let mut last_v = Object::Null;
-        for statement in block.into_iter() {
-            match statement {
-                Let(variable_name, value) => {
-                    if environment.borrow().variables.contains_key(variable_name) {
-                        return Err(format!("{variable_name} is initialized"));
-                    }
+        for (i, statement) in block.iter().enumerate() {
+            let tail = tail && i == block.len() - 1;
+            match self.eval_statement(statement, environment.clone(), tail) {
+                ControlFlow::Normal(v) => last_v = v,
+                flow => return flow,
+            }
+        }
+        ControlFlow::Normal(last_v)
+    }
 
-                    let v = match value {
-                        None => Object::Null,
-                        Some(expr) => {
-                            let v = self.eval_expression(environment.clone(), expr);
-                            match v {
-                                Err(e) => return Err(e),
-                                Ok(v) => v,
-                            }
-                        }
-                    };
-                    last_v = v.clone();
-                    environment
-                        .borrow_mut()
-                        .variables
-                        .insert(variable_name.clone(), v);
-                }
-                Return(return_value) => {
-                    let v = match return_value {
-                        None => Object::Null,
-                        Some(expr) => {
-                            let v = self.eval_expression(environment.clone(), expr);
-                            match v {
-                                Ok(obj) => obj,
-                                Err(e) => return Err(e),
-                            }
-                        }
-                    };
-                    if environment.borrow().in_function {
-                        return Ok(v);
-                    }
-                    return Ok(Object::Return(Box::new(v)));
-                }
-                Expression(expr) => match self.eval_expression(environment.clone(), expr) {
-                    Ok(obj) => {
-                        match obj {
-                            Object::Return(obj) => {
-                                if environment.borrow().in_function {
-                                    return Ok(*obj);
-                                }
-
-                                return Ok(Object::Return(obj));
-                            }
-                            _ => {}
-                        }
-                        last_v = obj;
-                    }
-                    Err(e) => return Err(e),
-                },
+    fn eval_statement(
+        &self,
+        statement: &Spanned<Statement>,
+        environment: Rc<RefCell<Environment>>,
+        tail: bool,
+    ) -> ControlFlow {
+        match &statement.node {
+            Let(variable_name, value) => self
+                .eval_let(variable_name, value, environment)
+                .map_err(|flow| flow.or_span(statement.span))
+                .into(),
+            Return(None) => ControlFlow::Return(Object::Null),
+            Return(Some(expr)) => match self.eval_tail(environment, expr) {
+                Ok(v) => ControlFlow::Return(v),
+                Err(flow) => flow,
+            },
+            Expression(expr) if tail => self.eval_tail(environment, expr).into(),
+            Expression(expr) => self.eval_expression(environment, expr).into(),
+            While {
+                label,
+                condition,
+                body,
+            } => self.eval_while(label, condition, body, environment).into(),
+            For {
+                label,
+                iterable,
+                body,
+                ..
+            } => self.eval_for(label, iterable, body, environment).into(),
+            Break(label) => ControlFlow::Break(label.clone()),
+            Continue(label) => ControlFlow::Continue(label.clone()),
+        }
+    }
+
+    fn eval_while(
+        &self,
+        label: &Option<String>,
+        condition: &Spanned<ExpressionStatement>,
+        body: &[Spanned<Statement>],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Object, ControlFlow> {
+        while self
+            .eval_expression(environment.clone(), condition)?
+            .is_truthy()
+        {
+            let loop_env = Environment::new(Some(environment.clone()));
+            if !self.eval_iteration(label, body, loop_env)? {
+                break;
+            }
+        }
+        Ok(Object::Null)
+    }
+
+    fn eval_for(
+        &self,
+        label: &Option<String>,
+        iterable: &Spanned<ExpressionStatement>,
+        body: &[Spanned<Statement>],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Object, ControlFlow> {
+        let values: Box<dyn Iterator<Item = Object>> = match &iterable.node {
+            // Ranges are counted through instead of being built as an array
+            Range { start, end } => {
+                let (start, end) = self
+                    .eval_range(environment.clone(), start, end)
+                    .map_err(|flow| flow.or_span(iterable.span))?;
+                Box::new((start..end).map(Object::Integer))
+            }
+            _ => {
+                let v = self.eval_expression(environment.clone(), iterable)?;
+                let values = v
+                    .iter_values()
+                    .map_err(|e| ControlFlow::from(e).or_span(iterable.span))?;
+                Box::new(values.into_iter())
+            }
+        };
+
+        // Every iteration gets its own scope, with the variable in its first
+        // slot, so closures created in the body capture that iteration's value
+        for value in values {
+            let loop_env = Environment::new(Some(environment.clone()));
+            loop_env.borrow_mut().slots.push(value);
+            if !self.eval_iteration(label, body, loop_env)? {
+                break;
+            }
+        }
+        Ok(Object::Null)
+    }
+
+    // Run the body of a loop once and tell whether the loop goes on. A break or
+    // continue for an outer loop, and a return, are left to unwind further
+    fn eval_iteration(
+        &self,
+        label: &Option<String>,
+        body: &[Spanned<Statement>],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<bool, ControlFlow> {
+        match self.eval_block(body, environment, false) {
+            ControlFlow::Normal(_) => Ok(true),
+            ControlFlow::Break(target) if target.is_none() || target == *label => Ok(false),
+            ControlFlow::Continue(target) if target.is_none() || target == *label => Ok(true),
+            flow => Err(flow),
+        }
+    }
+
+    fn eval_range(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        start: &Spanned<ExpressionStatement>,
+        end: &Spanned<ExpressionStatement>,
+    ) -> Result<(i64, i64), ControlFlow> {
+        let start = self.eval_expression(environment.clone(), start)?;
+        let end = self.eval_expression(environment, end)?;
+        match (start, end) {
+            (Object::Integer(start), Object::Integer(end)) => Ok((start, end)),
+            (left, right) => Err(RuntimeError::TypeMismatch {
+                operator: "..",
+                left,
+                right,
+            }
+            .into()),
+        }
+    }
+
+    fn eval_let(
+        &self,
+        variable_name: &str,
+        value: &Option<Spanned<ExpressionStatement>>,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Object, ControlFlow> {
+        // Top level variables are globals, defined under the number the
+        // resolver bound their uses to. The variables of a function or block
+        // scope take the next slot of the scope, in the order the resolver
+        // numbered them
+        let global = environment.borrow().outer.is_none();
+        if global && environment.borrow().variables.contains_key(variable_name) {
+            return Err(RuntimeError::AlreadyDeclared(variable_name.to_string()).into());
+        }
+
+        let v = match value {
+            None => Object::Null,
+            Some(expr) => self.eval_expression(environment.clone(), expr)?,
+        };
+        let mut environment = environment.borrow_mut();
+        if global {
+            environment
+                .variables
+                .insert(variable_name.to_string(), v.clone());
+        } else {
+            environment.slots.push(v.clone());
+        }
+        Ok(v)
+    }
+
+    // Call a function or builtin with already evaluated arguments
+    pub fn apply(&self, function: Object, args: Vec<Object>) -> Result<Object, InterpreterError> {
+        let function = match function {
+            Object::Function(function) => function,
+            Object::Builtin(builtin) => return Ok((builtin.func)(&args)?),
+            v => return Err(RuntimeError::NotCallable(v).into()),
+        };
+        Self::check_arity(&function, &args)?;
+
+        let depth = self.call_depth.get();
+        if depth >= self.max_call_depth {
+            return Err(RuntimeError::CallDepthExceeded(self.max_call_depth).into());
+        }
+        self.call_depth.set(depth + 1);
+        let result = self.call(function, args);
+        self.call_depth.set(depth);
+        result
+    }
+
+    fn check_arity(function: &Function, args: &[Object]) -> Result<(), RuntimeError> {
+        if function.params.len() != args.len() {
+            return Err(RuntimeError::ArgumentCount {
+                expected: function.params.len(),
+                found: args.len(),
+            });
+        }
+        Ok(())
+    }
+
+    // Run the body of a function, then the body of every function it tail
+    // calls in turn, without growing the stack
+    fn call(&self, function: Rc<Function>, args: Vec<Object>) -> Result<Object, InterpreterError> {
+        let (mut function, mut args) = (function, args);
+        loop {
+            // Arguments are bound in a fresh scope whose parent is the one the
+            // function was defined in
+            let fn_env = Environment::new(Some(function.env.clone()));
+            fn_env.borrow_mut().slots = args;
+
+            match self.eval_block(&function.body, fn_env, true) {
+                ControlFlow::Normal(v) | ControlFlow::Return(v) => return Ok(v),
+                ControlFlow::TailCall(next, next_args) => (function, args) = (next, next_args),
+                ControlFlow::Throw(e) => return Err(*e),
+                // A loop in the caller can not be broken out of from here
+                flow => return Err(Self::escaped(flow).into()),
             }
         }
-        Ok(last_v)
     }
 
+    // Evaluate an expression whose value is the result of the enclosing
+    // function. A call to a script function there is handed back to the
+    // running call as a TailCall instead of being made on top of it
+    fn eval_tail(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        expr: &Spanned<ExpressionStatement>,
+    ) -> Result<Object, ControlFlow> {
+        let result = match &expr.node {
+            Call { caller, args } => match self.eval_call_operands(environment, caller, args)? {
+                (Object::Function(function), values) => {
+                    Self::check_arity(&function, &values)?;
+                    Err(ControlFlow::TailCall(function, values))
+                }
+                (function, values) => Ok(self.apply(function, values)?),
+            },
+            If {
+                condition,
+                outcome,
+                alternate,
+            } => self.eval_if(environment, condition, outcome, alternate, true),
+            Group(inner) => self.eval_tail(environment, inner),
+            _ => self.eval_expression(environment, expr),
+        };
+        result.map_err(|flow| flow.or_span(expr.span))
+    }
+
+    // Callee and arguments of a call, both evaluated in the caller's scope
+    fn eval_call_operands(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        caller: &Spanned<ExpressionStatement>,
+        args: &[Spanned<ExpressionStatement>],
+    ) -> Result<(Object, Vec<Object>), ControlFlow> {
+        let function = self.eval_expression(environment.clone(), caller)?;
+        Ok((function, self.eval_list(environment, args)?))
+    }
+
+    // Shared by infix expressions and compound assignment
+    fn binary(operator: &Token, lhs: Object, rhs: Object) -> Result<Object, RuntimeError> {
+        match *operator {
+            Token::Plus => lhs + rhs,
+            Token::Minus => lhs - rhs,
+            Token::Asterisk => lhs * rhs,
+            Token::Slash => lhs / rhs,
+            Token::Percent => lhs % rhs,
+            Token::DoubleAsterisk => lhs.pow(rhs),
+            Token::DoubleSlash => lhs.floor_div(rhs),
+            Token::Ampersand => lhs & rhs,
+            Token::Pipe => lhs | rhs,
+            Token::Caret => lhs ^ rhs,
+            Token::ShiftLeft => lhs << rhs,
+            Token::ShiftRight => lhs >> rhs,
+            Token::Equal => Ok(Object::Boolean(lhs.equals(&rhs))),
+            Token::NotEqual => Ok(Object::Boolean(!lhs.equals(&rhs))),
+            Token::Gt => Ok(Object::Boolean(
+                lhs.compare(&rhs)? == Some(Ordering::Greater),
+            )),
+            Token::Gte => Ok(Object::Boolean(matches!(
+                lhs.compare(&rhs)?,
+                Some(Ordering::Greater | Ordering::Equal)
+            ))),
+            Token::Lt => Ok(Object::Boolean(lhs.compare(&rhs)? == Some(Ordering::Less))),
+            Token::Lte => Ok(Object::Boolean(matches!(
+                lhs.compare(&rhs)?,
+                Some(Ordering::Less | Ordering::Equal)
+            ))),
+            _ => Err(RuntimeError::UnknownOperator(operator.clone())),
+        }
+    }
+
+    // Assign to left[index], the container and index are already evaluated so
+    // that a compound assignment evaluates them only once
+    fn eval_index_assign(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        left: Object,
+        index: Object,
+        operator: &Option<Token>,
+        value: &Spanned<ExpressionStatement>,
+    ) -> Result<Object, ControlFlow> {
+        let current = match operator {
+            Some(_) => Some(left.index(&index)?),
+            None => None,
+        };
+        let mut v = self.eval_expression(environment, value)?;
+        if let (Some(operator), Some(current)) = (operator, current) {
+            v = Self::binary(operator, current, v)?;
+        }
+        left.set_index(&index, v.clone())?;
+        Ok(v)
+    }
+
+    // Errors without a location are attributed to the innermost expression
+    // they were raised from
     fn eval_expression(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        expr: &Spanned<ExpressionStatement>,
+    ) -> Result<Object, ControlFlow> {
+        self.eval_expression_node(environment, &expr.node)
+            .map_err(|flow| flow.or_span(expr.span))
+    }
+
+    // Arms that need more than a few locals live in their own methods, so that
+    // nesting one expression in another only costs the stack of the arms used
+    fn eval_expression_node(
         &self,
         environment: Rc<RefCell<Environment>>,
         expr: &ExpressionStatement,
-    ) -> Result<Object, String> {
+    ) -> Result<Object, ControlFlow> {
         match expr {
-            Prefix { operator, right } => {
-                let mut v = self.eval_expression(environment, right)?;
-                v = match operator {
-                    Token::Bang => match v {
-                        Object::Number(v) => {
-                            if v == 0 as f64 {
-                                Object::Number(1 as f64)
-                            } else {
-                                Object::Number(0 as f64)
-                            }
-                        }
-                        _ => {
-                            return Err(format!(
-                                "Invalid value, operatopr = {:?}, value = {:?}",
-                                operator, v
-                            ))
-                        }
-                    },
-                    Token::Minus => match v {
-                        Object::Number(v) => Object::Number(-v),
-                        _ => {
-                            return Err(format!(
-                                "Invalid value, operatopr = {:?}, value = {:?}",
-                                operator, v
-                            ))
-                        }
-                    },
-                    _ => {
-                        return Err(format!(
-                            "Invalid prefix operator, operatopr = {:?}",
-                            operator
-                        ))
-                    }
-                };
-                Ok(v)
-            }
+            Prefix { operator, right } => self.eval_prefix(environment, operator, right),
             Infix {
                 left,
                 operator,
                 right,
-            } => {
-                let lhs = self.eval_expression(environment.clone(), left)?;
-                let rhs = self.eval_expression(environment.clone(), right)?;
-                match *operator {
-                    Token::Plus => lhs + rhs,
-                    Token::Minus => lhs - rhs,
-                    // Token::Gt => lhs > rhs,
-                    // Token::Gte => lhs >= rhs,
-                    // Token::Lt => lhs < rhs,
-                    // Token::Lte => lhs <= rhs,
-                    _ => return Err(format!("Invalid infix operator {:?}", operator)),
-                }
-            }
+            } => self.eval_infix(environment, left, operator, right),
             If {
                 condition,
                 outcome,
                 alternate,
-            } => {
-                let cond =
-                    self.eval_expression(Environment::new(Some(environment.clone())), &condition)?;
-                match cond {
-                    Object::Number(0.0) => {
-                        self.eval_block(alternate, Environment::new(Some(environment.clone())))
-                    }
-                    _ => self.eval_block(outcome, Environment::new(Some(environment.clone()))),
-                }
+            } => self.eval_if(environment, condition, outcome, alternate, false),
+            Fn { args, body } => Ok(Object::Function(Rc::new(Function {
+                params: args.clone(),
+                body: body.clone(),
+                env: environment,
+            }))),
+            Call { caller, args } => self.eval_call(environment, caller, args),
+            Index { left, index } => self.eval_index(environment, left, index),
+            Slice { left, start, end } => self.eval_slice(environment, left, start, end),
+            Field { left, name } => self.eval_field(environment, left, name),
+            Assign {
+                target,
+                operator,
+                value,
+            } => self.eval_assign(environment, target, operator, value),
+            Range { start, end } => {
+                let (start, end) = self.eval_range(environment, start, end)?;
+                Ok(Object::new_array(
+                    (start..end).map(Object::Integer).collect(),
+                ))
             }
-            Fn { args, body } => Err("Unimplemented".to_string()),
-            Call { caller, args } => Err("Unimplemented".to_string()),
             Group(expr) => self.eval_expression(environment, expr),
-            Identifier(s) => match environment.borrow_mut().get(s) {
-                Ok(v) => Ok(v.to_owned()),
-                Err(e) => Err(e),
-            },
+            Array(elements) => self.eval_list(environment, elements).map(Object::new_array),
+            Hash(pairs) => self.eval_hash(environment, pairs),
+            Identifier(_, Binding::Global(index)) => Ok(environment.borrow().get_global(*index)?),
+            Identifier(_, Binding::Local { depth, slot }) => {
+                Ok(environment.borrow().get_local(*depth, *slot))
+            }
+            Identifier(_, Binding::Unresolved) => unreachable!("unresolved identifier"),
+            Int(num) => Ok(Object::Integer(*num)),
             Num(num) => Ok(Object::Number(*num)),
-            Bool(b) => {
-                if *b {
-                    Ok(Object::Number(1 as f64))
-                } else {
-                    Ok(Object::Number(0 as f64))
+            Str(s) => Ok(Object::String(s.clone())),
+            Bool(b) => Ok(Object::Boolean(*b)),
+        }
+    }
+
+    fn eval_prefix(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        operator: &Token,
+        right: &Spanned<ExpressionStatement>,
+    ) -> Result<Object, ControlFlow> {
+        let v = self.eval_expression(environment, right)?;
+        match operator {
+            Token::Bang => Ok(Object::Boolean(!v.is_truthy())),
+            Token::Minus => Ok((-v)?),
+            Token::Tilde => Ok(v.bit_not()?),
+            _ => Err(RuntimeError::UnknownOperator(operator.clone()).into()),
+        }
+    }
+
+    fn eval_infix(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        left: &Spanned<ExpressionStatement>,
+        operator: &Token,
+        right: &Spanned<ExpressionStatement>,
+    ) -> Result<Object, ControlFlow> {
+        let lhs = self.eval_expression(environment.clone(), left)?;
+        let rhs = self.eval_expression(environment, right)?;
+        Ok(Self::binary(operator, lhs, rhs)?)
+    }
+
+    fn eval_call(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        caller: &Spanned<ExpressionStatement>,
+        args: &[Spanned<ExpressionStatement>],
+    ) -> Result<Object, ControlFlow> {
+        let (function, values) = self.eval_call_operands(environment, caller, args)?;
+        Ok(self.apply(function, values)?)
+    }
+
+    fn eval_index(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        left: &Spanned<ExpressionStatement>,
+        index: &Spanned<ExpressionStatement>,
+    ) -> Result<Object, ControlFlow> {
+        let left = self.eval_expression(environment.clone(), left)?;
+        let index = self.eval_expression(environment, index)?;
+        Ok(left.index(&index)?)
+    }
+
+    fn eval_field(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        left: &Spanned<ExpressionStatement>,
+        name: &str,
+    ) -> Result<Object, ControlFlow> {
+        match self.eval_expression(environment, left)? {
+            left @ Object::Hash(_) => Ok(left.index(&Object::String(name.to_string()))?),
+            v => Err(RuntimeError::NoFields(v).into()),
+        }
+    }
+
+    // tail is set when the if is in tail position, see eval_tail
+    fn eval_if(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        condition: &Spanned<ExpressionStatement>,
+        outcome: &[Spanned<Statement>],
+        alternate: &[Spanned<Statement>],
+        tail: bool,
+    ) -> Result<Object, ControlFlow> {
+        let cond = self.eval_expression(environment.clone(), condition)?;
+        let block = if cond.is_truthy() { outcome } else { alternate };
+        self.eval_block(block, Environment::new(Some(environment)), tail)
+            .into_value()
+    }
+
+    fn eval_slice(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        left: &Spanned<ExpressionStatement>,
+        start: &Option<Box<Spanned<ExpressionStatement>>>,
+        end: &Option<Box<Spanned<ExpressionStatement>>>,
+    ) -> Result<Object, ControlFlow> {
+        let left = self.eval_expression(environment.clone(), left)?;
+        let start = match start {
+            None => None,
+            Some(start) => Some(self.eval_expression(environment.clone(), start)?),
+        };
+        let end = match end {
+            None => None,
+            Some(end) => Some(self.eval_expression(environment, end)?),
+        };
+        Ok(left.slice(start.as_ref(), end.as_ref())?)
+    }
+
+    fn eval_assign(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        target: &Spanned<ExpressionStatement>,
+        operator: &Option<Token>,
+        value: &Spanned<ExpressionStatement>,
+    ) -> Result<Object, ControlFlow> {
+        match &target.node {
+            Identifier(_, binding) => {
+                // For compound assignment the current value is read first
+                let current = match (operator, binding) {
+                    (None, _) => None,
+                    (Some(_), Binding::Global(index)) => {
+                        Some(environment.borrow().get_global(*index)?)
+                    }
+                    (Some(_), Binding::Local { depth, slot }) => {
+                        Some(environment.borrow().get_local(*depth, *slot))
+                    }
+                    (Some(_), Binding::Unresolved) => unreachable!("unresolved identifier"),
+                };
+                let mut v = self.eval_expression(environment.clone(), value)?;
+                if let (Some(operator), Some(current)) = (operator, current) {
+                    v = Self::binary(operator, current, v)?;
+                }
+                match binding {
+                    Binding::Global(index) => {
+                        environment.borrow_mut().set_global(*index, v.clone())?
+                    }
+                    Binding::Local { depth, slot } => {
+                        environment.borrow_mut().set_local(*depth, *slot, v.clone())
+                    }
+                    Binding::Unresolved => unreachable!("unresolved identifier"),
                 }
+                Ok(v)
+            }
+            Index { left, index } => {
+                let left = self.eval_expression(environment.clone(), left)?;
+                let index = self.eval_expression(environment.clone(), index)?;
+                self.eval_index_assign(environment, left, index, operator, value)
             }
+            Field { left, name } => match self.eval_expression(environment.clone(), left)? {
+                left @ Object::Hash(_) => {
+                    let index = Object::String(name.clone());
+                    self.eval_index_assign(environment, left, index, operator, value)
+                }
+                v => Err(RuntimeError::NoFields(v).into()),
+            },
+            _ => Err(RuntimeError::InvalidAssignmentTarget(target.node.clone()).into()),
+        }
+    }
+
+    fn eval_list(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        elements: &[Spanned<ExpressionStatement>],
+    ) -> Result<Vec<Object>, ControlFlow> {
+        let mut v = vec![];
+        for element in elements.iter() {
+            v.push(self.eval_expression(environment.clone(), element)?);
+        }
+        Ok(v)
+    }
+
+    fn eval_hash(
+        &self,
+        environment: Rc<RefCell<Environment>>,
+        pairs: &[(Spanned<ExpressionStatement>, Spanned<ExpressionStatement>)],
+    ) -> Result<Object, ControlFlow> {
+        let mut hash = object::Hash::new();
+        for (key, value) in pairs.iter() {
+            let key = self.eval_expression(environment.clone(), key)?;
+            let value = self.eval_expression(environment.clone(), value)?;
+            hash.insert(key, value)?;
         }
+        Ok(Object::new_hash(hash))
     }
 }
 
+#[cfg(test)]
 mod test {
     use super::*;
-    use crate::{evaluator, lexer, parser};
+    use crate::convert::arg;
+    use crate::{lexer, parser};
+
+    fn parse(input: &str) -> Program {
+        let (program, errors) = parser::Parser::new(lexer::Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "{input}: {:?}", errors);
+        program
+    }
 
     #[test]
     fn test_evaluator() {
@@ -238,7 +713,7 @@ mod test {
             Testcase {
                 name: "evaluate some add operations",
                 input: String::from("let a = 5"),
-                expected: Object::Number(5.0),
+                expected: Object::Integer(5),
             },
             Testcase {
                 name: "evaluate if",
@@ -248,25 +723,830 @@ mod test {
                     6
                 }",
                 ),
-                expected: Object::Number(6.0),
+                expected: Object::Integer(6),
+            },
+            Testcase {
+                name: "call function",
+                input: String::from(
+                    "let add = fn(a, b) { a + b };
+                    add(1, 2) + 3",
+                ),
+                expected: Object::Integer(6),
+            },
+            Testcase {
+                name: "return from nested if",
+                input: String::from(
+                    "let f = fn(n) {
+                        if n { return 1 }
+                        2
+                    };
+                    f(1) + f(0)",
+                ),
+                expected: Object::Integer(3),
+            },
+            Testcase {
+                name: "return from doubly nested if",
+                input: String::from(
+                    "let f = fn(n) {
+                        if n > 0 {
+                            if n > 1 { return 2 }
+                            return 1
+                        }
+                        0
+                    };
+                    f(5) * 100 + f(1) * 10 + f(0)",
+                ),
+                expected: Object::Integer(210),
+            },
+            Testcase {
+                name: "bare return from nested if",
+                input: String::from(
+                    "let f = fn(n) {
+                        if n > 0 {
+                            if n > 1 { return }
+                            return;
+                        }
+                        n
+                    };
+                    [f(2), f(1), f(0)]",
+                ),
+                expected: Object::new_array(vec![Object::Null, Object::Null, Object::Integer(0)]),
+            },
+            Testcase {
+                name: "return unwinds through the expression it appears in",
+                input: String::from(
+                    "let f = fn() {
+                        let x = if true { return 1 } else { 2 };
+                        x + 10
+                    };
+                    let y = f();
+                    y + [f()][0]",
+                ),
+                expected: Object::Integer(2),
+            },
+            Testcase {
+                name: "tail calls run in constant stack",
+                input: String::from(
+                    "let count = fn(n, acc) { if n == 0 { acc } else { count(n - 1, acc + 1) } };
+                    let even = fn(n) { if n == 0 { return true } return odd(n - 1) };
+                    let odd = fn(n) { if (n == 0) { false } else { (even(n - 1)) } };
+                    [count(100000, 0), even(100001)]",
+                ),
+                expected: Object::new_array(vec![Object::Integer(100000), Object::Boolean(false)]),
+            },
+            Testcase {
+                name: "adder captures argument of outer function",
+                input: String::from(
+                    "let adder = fn(x) { fn(y) { x + y } };
+                    let add5 = adder(5);
+                    add5(3) + adder(1)(1)",
+                ),
+                expected: Object::Integer(10),
+            },
+            Testcase {
+                name: "closure sees bindings added to its environment later",
+                input: String::from(
+                    "let get = fn() { later };
+                    let later = 7;
+                    get()",
+                ),
+                expected: Object::Integer(7),
+            },
+            Testcase {
+                name: "closure mutates captured state and sees it on later calls",
+                input: String::from(
+                    "let counter = fn() {
+                        let count = 0;
+                        fn() { count = count + 1; count }
+                    };
+                    let total = 0;
+                    let add = fn(n) { total += n; total };
+                    let next = counter();
+                    let other = counter();
+                    next(); next(); add(5);
+                    [next(), other(), add(2), total]",
+                ),
+                expected: Object::new_array(vec![
+                    Object::Integer(3),
+                    Object::Integer(1),
+                    Object::Integer(7),
+                    Object::Integer(7),
+                ]),
+            },
+            Testcase {
+                name: "recursive function",
+                input: String::from(
+                    "let sum = fn(n) { if n { n + sum(n - 1) } else { 0 } };
+                    sum(4)",
+                ),
+                expected: Object::Integer(10),
+            },
+            Testcase {
+                name: "comparison operators",
+                input: String::from("1 < 2"),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "comparison binds tighter than equality",
+                input: String::from("2 <= 2 == 3 > 4"),
+                expected: Object::Boolean(false),
+            },
+            Testcase {
+                name: "equality between different types",
+                input: String::from("1 == true"),
+                expected: Object::Boolean(false),
+            },
+            Testcase {
+                name: "function is only equal to itself",
+                input: String::from(
+                    "let f = fn() { 1 };
+                    let g = fn() { 1 };
+                    f == f != (f == g)",
+                ),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "zero is falsy",
+                input: String::from("if 0 { 1 } else { 2 }"),
+                expected: Object::Integer(2),
+            },
+            Testcase {
+                name: "null is falsy",
+                input: String::from(
+                    "let a;
+                    if a { 1 } else { 2 }",
+                ),
+                expected: Object::Integer(2),
+            },
+            Testcase {
+                name: "bang operator",
+                input: String::from("!0 == !!5"),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "string concatenation",
+                input: String::from(r#""foo" + "bar" == "foobar""#),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "string literal with escapes",
+                input: String::from(r#"let s = "a\tb"; s + r"\n""#),
+                expected: Object::String("a\tb\\n".to_string()),
+            },
+            Testcase {
+                name: "string comparison",
+                input: String::from(r#""apple" < "banana""#),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "array literal",
+                input: String::from("[1, 1 + 1, \"three\"]"),
+                expected: Object::new_array(vec![
+                    Object::Integer(1),
+                    Object::Integer(2),
+                    Object::String("three".to_string()),
+                ]),
+            },
+            Testcase {
+                name: "array indexing with negative index",
+                input: String::from(
+                    "let a = [1, 2, 3];
+                    a[0] * 10 + a[-1]",
+                ),
+                expected: Object::Integer(13),
+            },
+            Testcase {
+                name: "array slicing clamps bounds",
+                input: String::from(
+                    "let a = [1, 2, 3, 4];
+                    [a[1:3], a[-2:], a[:100], a[3:1]]",
+                ),
+                expected: Object::new_array(vec![
+                    Object::new_array(vec![Object::Integer(2), Object::Integer(3)]),
+                    Object::new_array(vec![Object::Integer(3), Object::Integer(4)]),
+                    Object::new_array(vec![
+                        Object::Integer(1),
+                        Object::Integer(2),
+                        Object::Integer(3),
+                        Object::Integer(4),
+                    ]),
+                    Object::new_array(vec![]),
+                ]),
+            },
+            Testcase {
+                name: "string indexing and slicing",
+                input: String::from("\"hello\"[-1] + \"hello\"[1:3]"),
+                expected: Object::String("oel".to_string()),
+            },
+            Testcase {
+                name: "index assignment is visible through aliases",
+                input: String::from(
+                    "let a = [1, 2];
+                    let b = a;
+                    b[-1] = 5;
+                    a",
+                ),
+                expected: Object::new_array(vec![Object::Integer(1), Object::Integer(5)]),
+            },
+            Testcase {
+                name: "hash lookup by string, number and boolean keys",
+                input: String::from(
+                    "let h = {\"one\": 1, 2: \"two\", true: 3};
+                    [h[\"one\"], h[2], h[1 == 1], h[\"missing\"]]",
+                ),
+                expected: Object::new_array(vec![
+                    Object::Integer(1),
+                    Object::String("two".to_string()),
+                    Object::Integer(3),
+                    Object::Null,
+                ]),
+            },
+            Testcase {
+                name: "hash keys of different types do not collide",
+                input: String::from("{1: \"number\", \"1\": \"string\"}[\"1\"]"),
+                expected: Object::String("string".to_string()),
+            },
+            Testcase {
+                name: "zero and negative zero share a key",
+                input: String::from("{0: 1}[-0.0]"),
+                expected: Object::Integer(1),
+            },
+            Testcase {
+                name: "hash assignment inserts and updates",
+                input: String::from(
+                    "let h = {\"a\": 1};
+                    h[\"a\"] = h[\"a\"] + 1;
+                    h[\"b\"] = 5;
+                    h == {\"b\": 5, \"a\": 2}",
+                ),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "integer arithmetic is exact",
+                input: String::from("9007199254740993 + 1"),
+                expected: Object::Integer(9007199254740994),
+            },
+            Testcase {
+                name: "mixed arithmetic promotes to float",
+                input: String::from("[1 + 2.0, 7 / 2, 6 / 3, 7.5 // 2, 2 ** -1]"),
+                expected: Object::new_array(vec![
+                    Object::Number(3.0),
+                    Object::Number(3.5),
+                    Object::Number(2.0),
+                    Object::Number(3.0),
+                    Object::Number(0.5),
+                ]),
+            },
+            Testcase {
+                name: "integer power stays an integer",
+                input: String::from("2 ** 62"),
+                expected: Object::Integer(4611686018427387904),
+            },
+            Testcase {
+                name: "integers and floats with the same value are equal",
+                input: String::from("1 == 1.0 == ([1, {2: 3}] == [1.0, {2.0: 3.0}])"),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "integral float shares hash key with integer",
+                input: String::from("{1: \"a\"}[1.0]"),
+                expected: Object::String("a".to_string()),
+            },
+            Testcase {
+                name: "bitwise operators",
+                input: String::from("[6 & 3 | 8 ^ 1, 1 << 1 + 1, ~5, -16 >> 2]"),
+                expected: Object::new_array(vec![
+                    Object::Integer(11),
+                    Object::Integer(4),
+                    Object::Integer(-6),
+                    Object::Integer(-4),
+                ]),
+            },
+            Testcase {
+                name: "arithmetic operators",
+                input: String::from("2 * 3 ** 2 - 10 / 4 + 7 // 2"),
+                expected: Object::Number(18.5),
+            },
+            Testcase {
+                name: "right associative power",
+                input: String::from("2 ** 3 ** 2"),
+                expected: Object::Integer(512),
+            },
+            Testcase {
+                name: "floored modulo takes sign of divisor",
+                input: String::from("(-7 % 3) * 10 + 7 % -3"),
+                expected: Object::Integer(18),
+            },
+            Testcase {
+                name: "floor division rounds toward negative infinity",
+                input: String::from("-7 // 2"),
+                expected: Object::Integer(-4),
+            },
+            Testcase {
+                name: "power of zero to negative exponent is infinity",
+                input: String::from("0 ** -1 > 1000000"),
+                expected: Object::Boolean(true),
+            },
+            Testcase {
+                name: "nan is not equal to itself",
+                input: String::from(
+                    "let nan = (0 - 1) ** 0.5;
+                    nan == nan",
+                ),
+                expected: Object::Boolean(false),
+            },
+            Testcase {
+                name: "assignment updates the nearest binding",
+                input: String::from(
+                    "let x = 1;
+                    let f = fn() { x = x + 10; x };
+                    let g = fn() { let x = 5; x = 6; x };
+                    [f(), g(), x]",
+                ),
+                expected: Object::new_array(vec![
+                    Object::Integer(11),
+                    Object::Integer(6),
+                    Object::Integer(11),
+                ]),
+            },
+            Testcase {
+                name: "assignment is an expression",
+                input: String::from(
+                    "let a = 0; let b = 0;
+                    a = b = 3;
+                    a + b",
+                ),
+                expected: Object::Integer(6),
+            },
+            Testcase {
+                name: "compound assignment",
+                input: String::from(
+                    "let x = 10;
+                    x += 5; x -= 3; x *= 2; x %= 7;
+                    let y = 7; y /= 2;
+                    let s = \"a\"; s += 1;
+                    [x, y, s]",
+                ),
+                expected: Object::new_array(vec![
+                    Object::Integer(3),
+                    Object::Number(3.5),
+                    Object::String("a1".to_string()),
+                ]),
+            },
+            Testcase {
+                name: "compound assignment to elements and fields",
+                input: String::from(
+                    "let a = [1, 2];
+                    let h = {\"n\": 1, \"inner\": {\"m\": 2}};
+                    a[-1] += 5;
+                    h.n += 1;
+                    h.inner.m *= 10;
+                    h.added = true;
+                    [a, h.n, h[\"inner\"].m, h.added, h.missing]",
+                ),
+                expected: Object::new_array(vec![
+                    Object::new_array(vec![Object::Integer(1), Object::Integer(7)]),
+                    Object::Integer(2),
+                    Object::Integer(20),
+                    Object::Boolean(true),
+                    Object::Null,
+                ]),
+            },
+            Testcase {
+                name: "variables can be assigned inside if blocks",
+                input: String::from(
+                    "let n = 0;
+                    if true { n = 1 }
+                    n",
+                ),
+                expected: Object::Integer(1),
+            },
+            Testcase {
+                name: "len of string, array and hash",
+                input: String::from(r#"len("héllo") + len([1, 2]) + len({1: 2})"#),
+                expected: Object::Integer(8),
+            },
+            Testcase {
+                name: "type names",
+                input: String::from(
+                    r#"[type(1), type(1.5), type("a"), type(true), type([]), type({}), type(fn() {}), type(len), type(println("x"))]"#,
+                ),
+                expected: Object::new_array(
+                    [
+                        "integer", "float", "string", "boolean", "array", "hash", "function",
+                        "builtin", "null",
+                    ]
+                    .iter()
+                    .map(|name| Object::String(name.to_string()))
+                    .collect(),
+                ),
+            },
+            Testcase {
+                name: "str formats values without quoting strings",
+                input: String::from(r#"str([1, 2.5, "a", {true: false}])"#),
+                expected: Object::String("[1, 2.5, a, {true: false}]".to_string()),
+            },
+            Testcase {
+                name: "repr quotes strings at every level",
+                input: String::from(r#"[repr("a"), repr([5.0, "b", {"c": "d"}])]"#),
+                expected: Object::new_array(vec![
+                    Object::String(r#""a""#.to_string()),
+                    Object::String(r#"[5, "b", {"c": "d"}]"#.to_string()),
+                ]),
+            },
+            Testcase {
+                name: "num parses integers and floats",
+                input: String::from(r#"[num(" 42 "), num("2.5e1"), num(true), num(3.5)]"#),
+                expected: Object::new_array(vec![
+                    Object::Integer(42),
+                    Object::Number(25.0),
+                    Object::Integer(1),
+                    Object::Number(3.5),
+                ]),
+            },
+            Testcase {
+                name: "script variables shadow builtins",
+                input: String::from(
+                    "let len = fn(x) { 0 };
+                    len([1, 2, 3])",
+                ),
+                expected: Object::Integer(0),
+            },
+            Testcase {
+                name: "builtins are values",
+                input: String::from(
+                    "let apply = fn(f, x) { f(x) };
+                    apply(len, \"abc\")",
+                ),
+                expected: Object::Integer(3),
+            },
+            Testcase {
+                name: "while loop",
+                input: String::from(
+                    "let i = 0; let total = 0;
+                    while i < 5 { i += 1; total += i }
+                    total",
+                ),
+                expected: Object::Integer(15),
+            },
+            Testcase {
+                name: "for loop over a range, an array, a string and hash keys",
+                input: String::from(
+                    "let out = \"\";
+                    for i in 1..3 { out += str(i) + \" \" }
+                    for x in [true, 1.5] { out += str(x) + \" \" }
+                    for c in \"ab\" { out += c + \" \" }
+                    for k in {\"k\": 1, 2: 3} { out += str(k) + \" \" }
+                    out",
+                ),
+                expected: Object::String("1 2 true 1.5 a b k 2 ".to_string()),
+            },
+            Testcase {
+                name: "range expression",
+                input: String::from("let n = 2; n - 1..n + 2"),
+                expected: Object::new_array(vec![
+                    Object::Integer(1),
+                    Object::Integer(2),
+                    Object::Integer(3),
+                ]),
+            },
+            Testcase {
+                name: "break and continue",
+                input: String::from(
+                    "let total = 0;
+                    for i in 0..100 {
+                        if i == 5 { break }
+                        if i % 2 == 0 { continue }
+                        total += i
+                    }
+                    total",
+                ),
+                expected: Object::Integer(4),
+            },
+            Testcase {
+                name: "labeled break and continue",
+                input: String::from(
+                    "let pairs = 0;
+                    outer: for i in 0..4 {
+                        for j in 0..4 {
+                            if j > i { continue outer }
+                            if i == 3 { break outer }
+                            pairs += 1
+                        }
+                    }
+                    pairs",
+                ),
+                expected: Object::Integer(6),
+            },
+            Testcase {
+                name: "return from inside a loop",
+                input: String::from(
+                    "let find = fn(xs, x) {
+                        let i = 0;
+                        for y in xs {
+                            if y == x { return i }
+                            i += 1
+                        }
+                        -1
+                    };
+                    find([3, 4, 5], 5) * 10 + find([], 1)",
+                ),
+                expected: Object::Integer(19),
+            },
+            Testcase {
+                name: "loop body can modify the array it iterates",
+                input: String::from(
+                    "let a = [1, 2];
+                    for x in a { a[0] = a[0] + x }
+                    a[0]",
+                ),
+                expected: Object::Integer(4),
+            },
+            Testcase {
+                name: "closures capture the iteration's variable",
+                input: String::from(
+                    "let fs = {};
+                    for i in 0..3 { fs[i] = fn() { i } }
+                    fs[0]() + fs[2]()",
+                ),
+                expected: Object::Integer(2),
             },
         ];
 
         for testcase in testcases.into_iter() {
             let evaluator = Evaluator::new();
             let env = Environment::new(None);
-            let lexer = lexer::Lexer::new(&testcase.input);
-            let mut parser = parser::Parser::new(lexer);
-            let program = match parser.parse_program() {
-                Err(e) => {
-                    println!("There was error during parsing, err={:?}", e);
-                    Program { statements: vec![] }
-                }
-                Ok(program) => program,
-            };
+            let program = parse(&testcase.input);
             let v = evaluator.eval(program, env.clone());
-            assert_eq!(v.is_ok(), true, "expected v to be ok, v={:?}", v);
+            assert!(
+                v.is_ok(),
+                "{}: expected v to be ok, v={:?}",
+                testcase.name,
+                v
+            );
             assert_eq!(v.unwrap(), testcase.expected);
         }
     }
+
+    #[test]
+    fn test_evaluator_errors() {
+        let testcases = vec![
+            (
+                "let f = fn(a) { a }; f(1, 2)",
+                "Expected 1 arguments, got 2",
+            ),
+            ("let f = 5; f()", "5 is not a function"),
+            ("[1, 2][2]", "Index 2 out of range for length 2"),
+            ("[1, 2][-3]", "Index -3 out of range for length 2"),
+            ("let a = [1]; a[1] = 0", "Index 1 out of range for length 1"),
+            ("[1, 2][0.5]", "Index must be an integer, got 0.5"),
+            ("5[0]", "5 is not indexable"),
+            (
+                "{[1]: 2}",
+                "Unhashable key [1], only numbers, strings and booleans can be hash keys",
+            ),
+            (
+                "let h = {}; h[fn() { 1 }] = 2",
+                "Unhashable key fn(), only numbers, strings and booleans can be hash keys",
+            ),
+            ("{}[0 ** -1 - 0 ** -1]", "NaN can not be used as a hash key"),
+            (
+                "9223372036854775807 + 1",
+                "Integer overflow in 9223372036854775807 + 1",
+            ),
+            (
+                "-9223372036854775807 - 2",
+                "Integer overflow in -9223372036854775807 - 2",
+            ),
+            ("2 ** 64", "Integer overflow in 2 ** 64"),
+            ("1 << 63", "Integer overflow in 1 << 63"),
+            ("1 << 64", "Shift amount 64 out of range 0..64"),
+            ("1.5 & 1", "Unsupported operand types for &: 1.5 and 1"),
+            ("~1.5", "Unsupported operand type for unary ~: 1.5"),
+            ("1 / 0", "Division by zero"),
+            ("1 // 0", "Division by zero"),
+            ("1 % 0", "Division by zero"),
+            ("true * 2", "Unsupported operand types for *: true and 2"),
+            ("1 < true", "Can not compare 1 with true"),
+            ("x = 1", "Can not assign to undeclared variable \"x\""),
+            ("len = 1", "Can not assign to undeclared variable \"len\""),
+            ("x += 1", "undefined variable \"x\""),
+            ("let a = [1]; a.b = 1", "[1] has no fields"),
+            ("let n = 5; n.b", "5 has no fields"),
+            (
+                "let h = {}; h.n += 1",
+                "Unsupported operand types for +: null and 1",
+            ),
+            ("len(1, 2)", "Expected 1 arguments, got 2"),
+            ("input(1, 2)", "Expected 1 arguments, got 2"),
+            ("len(5)", "len expected a string, array or hash, got 5"),
+            (
+                r#"num("abc")"#,
+                r#"num expected a numeric string, got "abc""#,
+            ),
+            ("break", "'break' outside loop"),
+            ("if true { continue }", "'continue' outside loop"),
+            (
+                "for i in 0..2 { break outer }",
+                "undefined loop label \"outer\"",
+            ),
+            (
+                "for i in 0..2 { let f = fn() { break }; f() }",
+                "'break' outside loop",
+            ),
+            ("let a = if true { return 1 }", "'return' outside function"),
+            (
+                "let f = fn() { let g = fn() { x }; let x = 1; g() }; f()",
+                "undefined variable \"x\"",
+            ),
+            ("for x in 5 { }", "5 is not iterable"),
+            (
+                "for x in 0..1.5 { }",
+                "Unsupported operand types for ..: 0 and 1.5",
+            ),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            let evaluator = Evaluator::new();
+            let env = Environment::new(None);
+            let program = parse(input);
+            assert_eq!(
+                evaluator.eval(program, env).map_err(|e| e.to_string()),
+                Err(expected.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_closure_shares_environment() {
+        let evaluator = Evaluator::new();
+        let env = Environment::new(None);
+        let eval = |input: &str| evaluator.eval(parse(input), env.clone());
+
+        // The counter reads count from the environment it was defined in, so
+        // storing each result back is seen by the next call
+        eval("let count = 0; let next = fn() { count + 1 }").unwrap();
+        for expected in [1, 2, 3] {
+            let v = eval("next()").unwrap();
+            assert_eq!(v, Object::Integer(expected));
+            env.borrow_mut().variables.insert("count".to_string(), v);
+        }
+    }
+
+    #[test]
+    fn test_evaluator_error_spans() {
+        let input = "let a = 1;\nlet b = a / 0";
+        let program = parse(input);
+        let err = Evaluator::new()
+            .eval(program, Environment::new(None))
+            .unwrap_err();
+        assert_eq!(
+            err.render(input),
+            "Division by zero at line 2, column 9\n2 | let b = a / 0\n  |         ^^^^^"
+        );
+
+        let testcases = vec![
+            ("1 + missing", (1, 5)),
+            ("let f = fn() {\n  [1][5]\n};\nf()", (2, 3)),
+            ("let a = 1; let a = 2", (1, 12)),
+            ("return 1", (1, 1)),
+            ("let a = 1;\nif a { break }", (2, 8)),
+            ("for x in\n  5 { }", (2, 3)),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            let program = parse(input);
+            let span = Evaluator::new()
+                .eval(program, Environment::new(None))
+                .err()
+                .and_then(|e| e.span());
+            assert_eq!(
+                span.map(|span| (span.line, span.column)),
+                Some(expected),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluator_error_kinds() {
+        let testcases = vec![
+            (
+                "missing",
+                RuntimeError::UndefinedVariable("missing".to_string()),
+            ),
+            (
+                "1 + true",
+                RuntimeError::TypeMismatch {
+                    operator: "+",
+                    left: Object::Integer(1),
+                    right: Object::Boolean(true),
+                },
+            ),
+            (
+                "let f = fn() { 1 }; f(2)",
+                RuntimeError::ArgumentCount {
+                    expected: 0,
+                    found: 1,
+                },
+            ),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            let program = parse(input);
+            let e = match Evaluator::new().eval(program, Environment::new(None)) {
+                Err(InterpreterError::Runtime(e, Some(_))) => e,
+                // Found by the resolver before anything runs
+                Err(InterpreterError::Syntax(errors)) => match &errors[..] {
+                    [InterpreterError::Resolve(e, _)] => e.clone(),
+                    errors => panic!("Expected one error for {input}, got {:?}", errors),
+                },
+                result => panic!("Expected error for {input}, got {:?}", result),
+            };
+            assert_eq!(e, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_max_call_depth(50);
+        let env = Environment::new(None);
+        let program = parse("let f = fn(n) { if n == 0 { 0 } else { 1 + f(n - 1) } }; f(49)");
+        assert_eq!(
+            evaluator.eval(program, env.clone()),
+            Ok(Object::Integer(49))
+        );
+
+        let v = evaluator.eval(parse("f(50)"), env.clone());
+        assert!(
+            matches!(
+                v,
+                Err(InterpreterError::Runtime(
+                    RuntimeError::CallDepthExceeded(50),
+                    Some(_)
+                ))
+            ),
+            "{:?}",
+            v
+        );
+
+        // The depth is unwound after the error
+        assert_eq!(evaluator.eval(parse("f(49)"), env), Ok(Object::Integer(49)));
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let env = Environment::new(None);
+        env.borrow_mut()
+            .register_fn("hypot", |args: &[Object]| -> Result<f64, RuntimeError> {
+                let x: f64 = arg(args, 0)?;
+                let y: f64 = arg(args, 1)?;
+                Ok(x.hypot(y))
+            });
+        env.borrow_mut().register_fn("words", |args: &[Object]| {
+            let s: String = arg(args, 0)?;
+            Ok::<_, RuntimeError>(s.split_whitespace().map(String::from).collect::<Vec<_>>())
+        });
+        env.borrow_mut()
+            .register_fn("fail", |_: &[Object]| Err::<(), _>("host failure"));
+
+        // Callbacks can share state with the host
+        let calls = Rc::new(RefCell::new(vec![]));
+        let log = calls.clone();
+        env.borrow_mut().register_fn("log", move |args: &[Object]| {
+            log.borrow_mut().push(arg::<i64>(args, 0)?);
+            Ok::<_, RuntimeError>(log.borrow().len() as i64)
+        });
+
+        let evaluator = Evaluator::new();
+        let v = evaluator.eval(
+            parse(r#"log(1); log(2); [hypot(3, 4.0), words(" a b "), log(3)]"#),
+            env.clone(),
+        );
+        assert_eq!(
+            v,
+            Ok(Object::new_array(vec![
+                Object::Number(5.0),
+                Object::from(vec!["a", "b"]),
+                Object::Integer(3),
+            ]))
+        );
+        assert_eq!(*calls.borrow(), vec![1, 2, 3]);
+
+        let testcases = vec![
+            ("fail()", "host failure"),
+            (r#"hypot("3", 4)"#, r#"Expected a number, got "3""#),
+            ("hypot(3)", "Expected 2 arguments, got 1"),
+            ("words()", "Expected 1 arguments, got 0"),
+        ];
+        for (input, expected) in testcases.into_iter() {
+            assert_eq!(
+                evaluator
+                    .eval(parse(input), env.clone())
+                    .map_err(|e| e.to_string()),
+                Err(expected.to_string())
+            );
+        }
+    }
 }
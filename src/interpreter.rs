@@ -0,0 +1,279 @@
+use crate::compiler;
+use crate::error::{InterpreterError, LexError, RuntimeError};
+use crate::evaluator::Evaluator;
+use crate::lexer::{Lexer, KEYWORDS};
+use crate::object::{Environment, Object};
+use crate::parser::{Parser, Program};
+use crate::resolver;
+use crate::token::Token;
+use crate::vm::Vm;
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// How sources are run, both give the same results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    // Walk the AST
+    #[default]
+    Evaluator,
+    // Compile to bytecode and run it on the vm
+    Vm,
+}
+
+// Entry point for embedding: owns a global environment that every evaluated
+// source shares, so definitions from one eval_str call are seen by the next
+pub struct Interpreter {
+    engine: Engine,
+    evaluator: Evaluator,
+    vm: Vm,
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_engine(Engine::default())
+    }
+
+    pub fn with_engine(engine: Engine) -> Self {
+        let env = Environment::new(None);
+        Self {
+            engine,
+            evaluator: Evaluator::new(),
+            vm: Vm::new(env.clone()),
+            env,
+        }
+    }
+
+    // Value of the last statement. Nothing is evaluated when the source has a
+    // syntax error, every lex and parse error is reported together, or when
+    // the resolver rejects it
+    pub fn eval_str(&mut self, source: &str) -> Result<Object, InterpreterError> {
+        let mut program = Self::parse(source)?;
+        match self.engine {
+            Engine::Evaluator => self.evaluator.eval(program, self.env.clone()),
+            Engine::Vm => {
+                let script = {
+                    let mut env = self.env.borrow_mut();
+                    resolver::resolve(&mut program, &mut env)?;
+                    compiler::compile(&program, &mut env.variables)?
+                };
+                self.vm.run(script)
+            }
+        }
+    }
+
+    // The tools of the cli below are hidden from the docs, they are not part
+    // of the embedding api
+
+    // Bytecode listing of source, see compiler::disassemble. It is checked
+    // as eval_str would, against the globals defined so far. The numbers it
+    // gives new globals are not kept
+    #[doc(hidden)]
+    pub fn disassemble(&self, source: &str) -> Result<String, InterpreterError> {
+        let mut program = Self::parse(source)?;
+        let mut env = self.env.borrow_mut();
+        let globals = env.variables.clone();
+        let listing = resolver::resolve(&mut program, &mut env)
+            .and_then(|_| compiler::compile(&program, &mut env.variables))
+            .map(|script| compiler::disassemble(&script, &env.variables));
+        env.variables = globals;
+        listing
+    }
+
+    // Source as the parser understood it, laid out by the printer
+    #[doc(hidden)]
+    pub fn format(source: &str) -> Result<String, InterpreterError> {
+        Ok(Self::parse(source)?.to_string())
+    }
+
+    // Whether both sources parse to the same program, ignoring layout and
+    // parentheses that don't change the meaning
+    #[doc(hidden)]
+    pub fn equivalent(source1: &str, source2: &str) -> bool {
+        match (Self::parse(source1), Self::parse(source2)) {
+            (Ok(program1), Ok(program2)) => program1.ungrouped() == program2.ungrouped(),
+            _ => false,
+        }
+    }
+
+    // Every token of source with the line and column it starts at, up to the
+    // end of input
+    #[doc(hidden)]
+    pub fn tokens(source: &str) -> String {
+        let mut lexer = Lexer::new(source);
+        let mut lines = vec![];
+        loop {
+            let (token, span) = lexer.next_token();
+            if token == Token::Eof {
+                break;
+            }
+            lines.push(format!("{}:{} {:?}", span.line, span.column, token));
+        }
+        lines.join("\n")
+    }
+
+    // Whether source needs more lines: a bracket or a string that may span
+    // lines is still open, or it ends in an operator or keyword that something
+    // has to follow
+    #[doc(hidden)]
+    pub fn is_incomplete(source: &str) -> bool {
+        let mut lexer = Lexer::new(source);
+        let mut depth = 0;
+        let mut last = Token::Eof;
+        loop {
+            let (token, span) = lexer.next_token();
+            match token {
+                Token::Eof => break,
+                Token::LParen | Token::LCurlyBracket | Token::LSquareBracket => depth += 1,
+                Token::RParen | Token::RCurlyBracket | Token::RSquareBracket => depth -= 1,
+                Token::Illegal(LexError::UnterminatedRawString) => return true,
+                Token::Illegal(LexError::UnterminatedString) => {
+                    return source[span.start..].starts_with("\"\"\"")
+                }
+                _ => {}
+            }
+            last = token;
+        }
+        let trailing = matches!(
+            last,
+            Token::Plus
+                | Token::Minus
+                | Token::Asterisk
+                | Token::Slash
+                | Token::Percent
+                | Token::DoubleAsterisk
+                | Token::DoubleSlash
+                | Token::Assign
+                | Token::PlusAssign
+                | Token::MinusAssign
+                | Token::AsteriskAssign
+                | Token::SlashAssign
+                | Token::PercentAssign
+                | Token::Equal
+                | Token::NotEqual
+                | Token::Bang
+                | Token::Gt
+                | Token::Lt
+                | Token::Gte
+                | Token::Lte
+                | Token::Ampersand
+                | Token::Pipe
+                | Token::Caret
+                | Token::Tilde
+                | Token::ShiftLeft
+                | Token::ShiftRight
+                | Token::Comma
+                | Token::Dot
+                | Token::DotDot
+                | Token::Colon
+                | Token::If
+                | Token::Else
+                | Token::Let
+                | Token::Fn
+                | Token::While
+                | Token::For
+                | Token::In
+        );
+        depth > 0 || trailing
+    }
+
+    // Words the language reserves, so they can be completed like names
+    #[doc(hidden)]
+    pub fn keywords() -> Vec<String> {
+        KEYWORDS
+            .iter()
+            .map(|(keyword, _)| keyword.to_string())
+            .collect()
+    }
+
+    fn parse(source: &str) -> Result<Program, InterpreterError> {
+        let (program, errors) = Parser::new(Lexer::new(source)).parse_program();
+        if !errors.is_empty() {
+            return Err(InterpreterError::Syntax(errors));
+        }
+        Ok(program)
+    }
+
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Object, InterpreterError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|e| InterpreterError::Io {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        self.eval_str(&source)
+    }
+
+    // Deepest nesting of non-tail calls before a RuntimeError is raised
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.evaluator.set_max_call_depth(depth);
+        self.vm.set_max_call_depth(depth);
+    }
+
+    // Define or overwrite a global variable
+    pub fn set_global(&mut self, name: &str, value: impl Into<Object>) {
+        let mut env = self.env.borrow_mut();
+        let index = env.variables.index(name);
+        env.variables.set(index, value.into());
+    }
+
+    // Global variable or builtin, None when undefined
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        let mut env = self.env.borrow_mut();
+        let index = env.variables.index(name);
+        env.get_global(index).ok()
+    }
+
+    // Every global variable by name, builtins are not included
+    pub fn globals(&self) -> Vec<(String, Object)> {
+        let mut globals: Vec<(String, Object)> = self
+            .env
+            .borrow()
+            .variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    // Names of the builtins, including the ones registered by the host
+    pub fn builtins(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.env.borrow().builtins.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Call a global function (or builtin) by name
+    pub fn call_function(
+        &mut self,
+        name: &str,
+        args: &[Object],
+    ) -> Result<Object, InterpreterError> {
+        let function = self
+            .get_global(name)
+            .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))?;
+        match self.engine {
+            Engine::Evaluator => self.evaluator.apply(function, args.to_vec()),
+            Engine::Vm => self.vm.call(function, args.to_vec()),
+        }
+    }
+
+    // See Environment::register_fn, arguments are read with arg
+    pub fn register_fn<F, R, E>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&[Object]) -> Result<R, E> + 'static,
+        R: Into<Object>,
+        E: Into<RuntimeError>,
+    {
+        self.env.borrow_mut().register_fn(name, func);
+    }
+}
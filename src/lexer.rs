@@ -1,8 +1,29 @@
-use crate::token::Token;
+use crate::{error::LexError, span::Span, token::Token};
+
+// Words that are tokens of their own rather than identifiers
+pub const KEYWORDS: [(&str, Token); 12] = [
+    ("let", Token::Let),
+    ("fn", Token::Fn),
+    ("if", Token::If),
+    ("else", Token::Else),
+    ("return", Token::Return),
+    ("while", Token::While),
+    ("for", Token::For),
+    ("in", Token::In),
+    ("break", Token::Break),
+    ("continue", Token::Continue),
+    ("true", Token::True),
+    ("false", Token::False),
+];
+
 pub struct Lexer {
     cur_char: char,
     position: usize,
     input: Vec<char>,
+    // Location of cur_char, offset is in bytes
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
 impl Lexer {
@@ -11,19 +32,69 @@ impl Lexer {
             cur_char: '\0',
             position: 0,
             input: input.chars().collect(),
+            offset: 0,
+            line: 1,
+            column: 1,
         };
         ret.read_char();
-        return ret;
+        ret.skip_shebang();
+        ret.skip_whitespace();
+        ret
     }
 
-    pub fn next_token(&mut self) -> Token {
+    pub fn next_token(&mut self) -> (Token, Span) {
+        let mut span = Span {
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            column: self.column,
+        };
         let token = match self.cur_char {
             '\0' => Token::Eof,
 
-            '+' => Token::Plus,
-            '-' => Token::Minus,
-            '*' => Token::Asterisk,
-            '/' => Token::Slash,
+            '+' => match self.peek_char() {
+                '=' => {
+                    self.read_char();
+                    Token::PlusAssign
+                }
+                _ => Token::Plus,
+            },
+            '-' => match self.peek_char() {
+                '=' => {
+                    self.read_char();
+                    Token::MinusAssign
+                }
+                _ => Token::Minus,
+            },
+            '*' => match self.peek_char() {
+                '*' => {
+                    self.read_char();
+                    Token::DoubleAsterisk
+                }
+                '=' => {
+                    self.read_char();
+                    Token::AsteriskAssign
+                }
+                _ => Token::Asterisk,
+            },
+            '/' => match self.peek_char() {
+                '/' => {
+                    self.read_char();
+                    Token::DoubleSlash
+                }
+                '=' => {
+                    self.read_char();
+                    Token::SlashAssign
+                }
+                _ => Token::Slash,
+            },
+            '%' => match self.peek_char() {
+                '=' => {
+                    self.read_char();
+                    Token::PercentAssign
+                }
+                _ => Token::Percent,
+            },
             '=' => {
                 if self.peek_char() == '=' {
                     self.read_char();
@@ -40,35 +111,61 @@ impl Lexer {
                     Token::Bang
                 }
             }
-            '>' => {
-                if self.peek_char() == '=' {
+            '>' => match self.peek_char() {
+                '=' => {
                     self.read_char();
                     Token::Gte
-                } else {
-                    Token::Gt
                 }
-            }
-            '<' => {
-                if self.peek_char() == '=' {
+                '>' => {
+                    self.read_char();
+                    Token::ShiftRight
+                }
+                _ => Token::Gt,
+            },
+            '<' => match self.peek_char() {
+                '=' => {
                     self.read_char();
                     Token::Lte
-                } else {
-                    Token::Lt
                 }
-            }
+                '<' => {
+                    self.read_char();
+                    Token::ShiftLeft
+                }
+                _ => Token::Lt,
+            },
+            '&' => Token::Ampersand,
+            '|' => Token::Pipe,
+            '^' => Token::Caret,
+            '~' => Token::Tilde,
 
             ',' => Token::Comma,
+            '.' => match self.peek_char() {
+                '.' => {
+                    self.read_char();
+                    Token::DotDot
+                }
+                _ => Token::Dot,
+            },
+            ':' => Token::Colon,
             ';' => Token::Semicolon,
             '(' => Token::LParen,
             ')' => Token::RParen,
             '{' => Token::LCurlyBracket,
             '}' => Token::RCurlyBracket,
+            '[' => Token::LSquareBracket,
+            ']' => Token::RSquareBracket,
 
             '0'..='9' => {
                 let mut num = String::from(self.cur_char);
                 loop {
                     let next_char = self.peek_char();
-                    if next_char.is_alphanumeric() || next_char == '.' {
+                    // A dot followed by another one starts a range: 0..10
+                    let is_fraction = next_char == '.' && self.peek_nth_char(1) != '.';
+                    // The exponent can be signed: 1e-5
+                    let is_exponent_sign = matches!(next_char, '+' | '-')
+                        && num.ends_with(['e', 'E'])
+                        && self.peek_nth_char(1).is_ascii_digit();
+                    if next_char.is_alphanumeric() || is_fraction || is_exponent_sign {
                         num.push(next_char);
                         self.read_char();
                     } else {
@@ -76,16 +173,30 @@ impl Lexer {
                     }
                 }
 
-                let token: Token;
-                match num.parse() {
-                    Ok(f) => token = Token::Num(f),
-                    Err(e) => {
-                        eprintln!("parse float error = {e}");
-                        token = Token::Illegal
+                // 42 is an integer while 42.0 and 4.2e1 are floats
+                if num.contains(['.', 'e', 'E']) {
+                    match num.parse() {
+                        Ok(f) => Token::Num(f),
+                        Err(e) => Token::Illegal(LexError::InvalidNumber {
+                            literal: num,
+                            reason: e.to_string(),
+                        }),
+                    }
+                } else {
+                    match num.parse() {
+                        Ok(i) => Token::Int(i),
+                        Err(e) => Token::Illegal(LexError::InvalidNumber {
+                            literal: num,
+                            reason: e.to_string(),
+                        }),
                     }
                 }
-
-                token
+            }
+            '"' => self.read_string(),
+            'r' if self.peek_char() == '"' => {
+                // Skip through r prefix
+                self.read_char();
+                self.read_raw_string()
             }
             'a'..='z' | 'A'..='Z' => {
                 let mut literal = String::from(self.cur_char);
@@ -104,10 +215,115 @@ impl Lexer {
 
                 Lexer::literal_to_token(&literal)
             }
-            _ => Token::Illegal,
+            _ => Token::Illegal(LexError::IllegalCharacter(self.cur_char)),
         };
+        if token != Token::Eof {
+            // cur_char is the last character of the token
+            span.end = self.offset + self.cur_char.len_utf8();
+        }
 
         self.read_char();
+        self.skip_whitespace();
+        (token, span)
+    }
+
+    // Read a "..." or """...""" string starting at the opening quote, cur_char is
+    // left at the closing quote. Only triple quoted strings may span lines
+    fn read_string(&mut self) -> Token {
+        let triple_quoted = self.peek_char() == '"' && self.peek_nth_char(1) == '"';
+        if triple_quoted {
+            // Skip through the other two opening quotes
+            self.read_char();
+            self.read_char();
+        }
+
+        let mut literal = String::new();
+        loop {
+            self.read_char();
+            match self.cur_char {
+                '\0' => return Token::Illegal(LexError::UnterminatedString),
+                '\n' if !triple_quoted => return Token::Illegal(LexError::UnterminatedString),
+                '"' if !triple_quoted => break,
+                '"' if self.peek_char() == '"' && self.peek_nth_char(1) == '"' => {
+                    // Skip through the other two closing quotes
+                    self.read_char();
+                    self.read_char();
+                    break;
+                }
+                '\\' => {
+                    self.read_char();
+                    match self.read_escape() {
+                        Ok(c) => literal.push(c),
+                        Err(e) => return Token::Illegal(e),
+                    }
+                }
+                c => literal.push(c),
+            }
+        }
+
+        Token::Str(literal)
+    }
+
+    // Read a r"..." string starting at the opening quote, backslashes are kept as is
+    fn read_raw_string(&mut self) -> Token {
+        let mut literal = String::new();
+        loop {
+            self.read_char();
+            match self.cur_char {
+                '\0' => return Token::Illegal(LexError::UnterminatedRawString),
+                '"' => break,
+                c => literal.push(c),
+            }
+        }
+
+        Token::Str(literal)
+    }
+
+    // Translate the escape sequence whose first character (after the backslash)
+    // is cur_char, cur_char is left at the last character of the sequence
+    fn read_escape(&mut self) -> Result<char, LexError> {
+        match self.cur_char {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            '"' => Ok('"'),
+            '\\' => Ok('\\'),
+            'u' => {
+                if self.peek_char() != '{' {
+                    return Err(LexError::MissingUnicodeBrace);
+                }
+                // Skip through u
+                self.read_char();
+
+                let mut hex = String::new();
+                loop {
+                    self.read_char();
+                    match self.cur_char {
+                        '}' => break,
+                        c if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+                        c => return Err(LexError::InvalidUnicodeCharacter(c)),
+                    }
+                }
+
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(LexError::InvalidUnicodeEscape(hex))
+            }
+            '\0' => Err(LexError::UnterminatedString),
+            c => Err(LexError::UnknownEscape(c)),
+        }
+    }
+
+    // A #! line at the start lets a script run as an executable
+    fn skip_shebang(&mut self) {
+        if self.cur_char == '#' && self.peek_char() == '!' {
+            while self.cur_char != '\n' && self.cur_char != '\0' {
+                self.read_char();
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
         while self.cur_char == ' '
             || self.cur_char == '\t'
             || self.cur_char == '\r'
@@ -115,36 +331,45 @@ impl Lexer {
         {
             self.read_char();
         }
-        return token;
     }
 
     fn read_char(&mut self) {
+        if self.position > 0 && self.position <= self.input.len() {
+            // Move the location past cur_char, which is a character of the input
+            self.offset += self.cur_char.len_utf8();
+            if self.cur_char == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+
         if self.position >= self.input.len() {
             self.cur_char = '\0';
+            // Mark the last character as consumed so the location stops at the end
+            self.position = self.input.len() + 1;
         } else {
             self.cur_char = self.input[self.position];
             self.position += 1;
         }
     }
 
-    fn peek_char(&mut self) -> char {
-        if self.position >= self.input.len() {
-            return '\0';
-        } else {
-            return self.input[self.position];
+    fn peek_char(&self) -> char {
+        self.peek_nth_char(0)
+    }
+
+    fn peek_nth_char(&self, n: usize) -> char {
+        match self.input.get(self.position + n) {
+            None => '\0',
+            Some(c) => *c,
         }
     }
 
     fn literal_to_token(literal: &str) -> Token {
-        match literal {
-            "let" => Token::Let,
-            "fn" => Token::Fn,
-            "if" => Token::If,
-            "else" => Token::Else,
-            "return" => Token::Return,
-            "true" => Token::True,
-            "false" => Token::False,
-            _ => Token::Ident(literal.to_string()),
+        match KEYWORDS.iter().find(|(keyword, _)| *keyword == literal) {
+            Some((_, token)) => token.clone(),
+            None => Token::Ident(literal.to_string()),
         }
     }
 }
@@ -152,6 +377,7 @@ impl Lexer {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::object::Object;
 
     #[test]
     fn test_lexer() {
@@ -161,7 +387,7 @@ mod test {
         }
         let testcases = vec![
             Testcase {
-                input: String::from("+-*/==="),
+                input: String::from("+-*/ ==="),
                 expected: vec![
                     Token::Plus,
                     Token::Minus,
@@ -171,6 +397,102 @@ mod test {
                     Token::Assign,
                 ],
             },
+            Testcase {
+                input: String::from("* ** / // %"),
+                expected: vec![
+                    Token::Asterisk,
+                    Token::DoubleAsterisk,
+                    Token::Slash,
+                    Token::DoubleSlash,
+                    Token::Percent,
+                ],
+            },
+            Testcase {
+                input: String::from("& | ^ ~ << >> <= >="),
+                expected: vec![
+                    Token::Ampersand,
+                    Token::Pipe,
+                    Token::Caret,
+                    Token::Tilde,
+                    Token::ShiftLeft,
+                    Token::ShiftRight,
+                    Token::Lte,
+                    Token::Gte,
+                ],
+            },
+            Testcase {
+                input: String::from("42 42.0 1e3"),
+                expected: vec![Token::Int(42), Token::Num(42.0), Token::Num(1000.0)],
+            },
+            Testcase {
+                input: String::from("1e-5 2.5E+2 1e-x"),
+                expected: vec![
+                    Token::Num(1e-5),
+                    Token::Num(250.0),
+                    Token::Illegal(LexError::InvalidNumber {
+                        literal: String::from("1e"),
+                        reason: String::from("invalid float literal"),
+                    }),
+                    Token::Minus,
+                    Token::Ident("x".to_string()),
+                ],
+            },
+            Testcase {
+                input: String::from("a[1:]"),
+                expected: vec![
+                    Token::Ident("a".to_string()),
+                    Token::LSquareBracket,
+                    Token::Int(1),
+                    Token::Colon,
+                    Token::RSquareBracket,
+                ],
+            },
+            Testcase {
+                input: String::from("x += 1; x -= a.b *= 2 /= 3 %= 4 // 5 ** 6"),
+                expected: vec![
+                    Token::Ident("x".to_string()),
+                    Token::PlusAssign,
+                    Token::Int(1),
+                    Token::Semicolon,
+                    Token::Ident("x".to_string()),
+                    Token::MinusAssign,
+                    Token::Ident("a".to_string()),
+                    Token::Dot,
+                    Token::Ident("b".to_string()),
+                    Token::AsteriskAssign,
+                    Token::Int(2),
+                    Token::SlashAssign,
+                    Token::Int(3),
+                    Token::PercentAssign,
+                    Token::Int(4),
+                    Token::DoubleSlash,
+                    Token::Int(5),
+                    Token::DoubleAsterisk,
+                    Token::Int(6),
+                ],
+            },
+            Testcase {
+                input: String::from("outer: for i in 0..10 { while x { break outer } continue }"),
+                expected: vec![
+                    Token::Ident("outer".to_string()),
+                    Token::Colon,
+                    Token::For,
+                    Token::Ident("i".to_string()),
+                    Token::In,
+                    Token::Int(0),
+                    Token::DotDot,
+                    Token::Int(10),
+                    Token::LCurlyBracket,
+                    Token::While,
+                    Token::Ident("x".to_string()),
+                    Token::LCurlyBracket,
+                    Token::Break,
+                    Token::Ident("outer".to_string()),
+                    Token::RCurlyBracket,
+                    Token::Continue,
+                    Token::RCurlyBracket,
+                ],
+            },
             Testcase {
                 input: "SON   TUNG".to_string(),
                 expected: vec![
@@ -180,19 +502,47 @@ mod test {
             },
             Testcase {
                 input: "12345".to_string(),
-                expected: vec![Token::Num(12345 as f64)],
+                expected: vec![Token::Int(12345)],
             },
             Testcase {
                 input: "12345.456".to_string(),
                 expected: vec![Token::Num(12345.456)],
             },
+            Testcase {
+                input: String::from(r#"  "hello" "a\"b\\c\n\t" "\u{48}\u{1F600}" """#),
+                expected: vec![
+                    Token::Str("hello".to_string()),
+                    Token::Str("a\"b\\c\n\t".to_string()),
+                    Token::Str("H\u{1F600}".to_string()),
+                    Token::Str(String::new()),
+                ],
+            },
+            Testcase {
+                input: String::from(r#"r"C:\dir\n" r + "x""#),
+                expected: vec![
+                    Token::Str("C:\\dir\\n".to_string()),
+                    Token::Ident("r".to_string()),
+                    Token::Plus,
+                    Token::Str("x".to_string()),
+                ],
+            },
+            Testcase {
+                input: String::from(
+                    r#""""first "line"
+second\tline""" """""""#,
+                ),
+                expected: vec![
+                    Token::Str("first \"line\"\nsecond\tline".to_string()),
+                    Token::Str(String::new()),
+                ],
+            },
             Testcase {
                 input: "let x = 5".to_string(),
                 expected: vec![
                     Token::Let,
                     Token::Ident("x".to_string()),
                     Token::Assign,
-                    Token::Num(5 as f64),
+                    Token::Int(5),
                 ],
             },
             Testcase {
@@ -201,9 +551,9 @@ mod test {
                     Token::Let,
                     Token::Ident("a".to_string()),
                     Token::Assign,
-                    Token::Num(5 as f64),
+                    Token::Int(5),
                     Token::Plus,
-                    Token::Num(6 as f64),
+                    Token::Int(6),
                 ],
             },
             Testcase {
@@ -212,7 +562,7 @@ mod test {
                     Token::If,
                     Token::Ident("a".to_string()),
                     Token::Equal,
-                    Token::Num(5 as f64),
+                    Token::Int(5),
                 ],
             },
         ];
@@ -221,7 +571,7 @@ mod test {
             let mut lexer = Lexer::new(&testcase.input);
             let mut i = 0;
             loop {
-                let token = lexer.next_token();
+                let (token, _) = lexer.next_token();
                 if token == Token::Eof {
                     break;
                 }
@@ -231,4 +581,92 @@ mod test {
             assert_eq!(i, testcase.expected.len())
         }
     }
+
+    #[test]
+    fn test_lexer_string_errors() {
+        let testcases = vec![
+            (r#""abc"#, LexError::UnterminatedString),
+            ("\"abc\ndef\"", LexError::UnterminatedString),
+            (r#"r"abc"#, LexError::UnterminatedRawString),
+            (r#""""abc""#, LexError::UnterminatedString),
+            (r#""\q""#, LexError::UnknownEscape('q')),
+            (
+                r#""\u{110000}""#,
+                LexError::InvalidUnicodeEscape("110000".to_string()),
+            ),
+            (r#""\u{zz}""#, LexError::InvalidUnicodeCharacter('z')),
+            (r#""\u41""#, LexError::MissingUnicodeBrace),
+            (
+                "1.5.2",
+                LexError::InvalidNumber {
+                    literal: "1.5.2".to_string(),
+                    reason: "invalid float literal".to_string(),
+                },
+            ),
+            ("#", LexError::IllegalCharacter('#')),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(lexer.next_token().0, Token::Illegal(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_lexer_spans() {
+        let mut lexer = Lexer::new("let a = \"ü\";\n  x >= 10");
+        let expected = vec![
+            (Token::Let, (0, 3, 1, 1)),
+            (Token::Ident("a".to_string()), (4, 5, 1, 5)),
+            (Token::Assign, (6, 7, 1, 7)),
+            (Token::Str("ü".to_string()), (8, 12, 1, 9)),
+            (Token::Semicolon, (12, 13, 1, 12)),
+            (Token::Ident("x".to_string()), (16, 17, 2, 3)),
+            (Token::Gte, (18, 20, 2, 5)),
+            (Token::Int(10), (21, 23, 2, 8)),
+            (Token::Eof, (23, 23, 2, 10)),
+        ];
+
+        for (token, (start, end, line, column)) in expected.into_iter() {
+            let span = Span {
+                start,
+                end,
+                line,
+                column,
+            };
+            assert_eq!(lexer.next_token(), (token, span));
+        }
+    }
+
+    #[test]
+    fn test_lexer_shebang() {
+        let mut lexer = Lexer::new("#!/usr/bin/env interpreter\nx");
+        let span = Span {
+            start: 27,
+            end: 28,
+            line: 2,
+            column: 1,
+        };
+        assert_eq!(lexer.next_token(), (Token::Ident("x".to_string()), span));
+        assert_eq!(lexer.next_token().0, Token::Eof);
+
+        // Only the first line can be a shebang
+        let mut lexer = Lexer::new("x\n#!");
+        assert_eq!(lexer.next_token().0, Token::Ident("x".to_string()));
+        assert_eq!(
+            lexer.next_token().0,
+            Token::Illegal(LexError::IllegalCharacter('#'))
+        );
+    }
+
+    #[test]
+    fn test_lexer_number_round_trip() {
+        // Printed floats read back as the same value
+        for num in [0.1, -2.5, 1.5e-7, 2.5e-300, 1e21, 1.25e300] {
+            let printed = Object::Number(num).to_string();
+            let mut lexer = Lexer::new(printed.trim_start_matches('-'));
+            assert_eq!(lexer.next_token().0, Token::Num(num.abs()), "{printed}");
+            assert_eq!(lexer.next_token().0, Token::Eof, "{printed}");
+        }
+    }
 }
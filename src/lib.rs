@@ -0,0 +1,20 @@
+pub(crate) mod builtin;
+pub(crate) mod compiler;
+mod convert;
+pub(crate) mod error;
+pub(crate) mod evaluator;
+mod interpreter;
+pub(crate) mod lexer;
+pub(crate) mod object;
+pub(crate) mod parser;
+pub(crate) mod resolver;
+pub(crate) mod span;
+pub(crate) mod statement;
+pub(crate) mod token;
+pub(crate) mod vm;
+
+pub use convert::arg;
+pub use error::{CompileError, InterpreterError, LexError, ParseError, RuntimeError};
+pub use interpreter::{Engine, Interpreter};
+pub use object::Object;
+pub use span::Span;
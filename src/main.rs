@@ -1,38 +1,675 @@
-mod evaluator;
-mod lexer;
-mod object;
-mod parser;
-mod statement;
-mod token;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
 
-use std::io;
+use interpreter::{Engine, Interpreter, InterpreterError, Object, RuntimeError};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::FileHistory, validate::Validator, Config, Context, Editor, Helper,
+};
 
-use lexer::Lexer;
-use parser::{Parser, Program};
+// Every script call takes several native frames, so the interpreter runs on a
+// thread with room for the default maximum call depth
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+const USAGE: &str =
+    "usage: interpreter [--engine=eval|vm] [--disassemble] [-i] [script.mk | -e code | -] [args...]
+       interpreter fmt [--check] <file>...
+
+Runs the script, the code given with -e or the script read from stdin with -,
+then starts the repl when -i is given. Without a script the repl starts. The
+script reads args with args()";
+
+// Exit statuses of a script that fails, as in sysexits.h
+const EXIT_SYNTAX_ERROR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_RUNTIME_ERROR: i32 = 70;
+
+// Script run when the repl starts, and again on :reset
+const RC_FILE: &str = ".interpreterrc";
+
+// Lines entered in the repl, kept across sessions
+const HISTORY_FILE: &str = ".interpreter_history";
+const HISTORY_SIZE: usize = 1000;
+
+const PROMPT: &str = ">> ";
+// Shown while the input so far is incomplete
+const CONTINUATION_PROMPT: &str = ".. ";
+
+const COMMANDS: &str = ":tokens <source>, :ast <source>, :env, :load <file>, :reset, :quit";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    engine: Engine,
+    // Print the bytecode of every input before running it
+    disassemble: bool,
+    // Arguments following the script, returned by args()
+    args: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Script {
+    File(String),
+    // Given with -e
+    Code(String),
+    Stdin,
+}
+
+#[derive(Debug, PartialEq)]
+struct Cli {
+    options: Options,
+    // The repl starts when there is none
+    script: Option<Script>,
+    // Start the repl after the script
+    interactive: bool,
+    help: bool,
+}
 
 fn main() {
-    print!("---huytq intepreter---");
-    let evaluator = evaluator::Evaluator::new();
-    let env = object::Environment::new(None);
+    if env::args().nth(1).as_deref() == Some("fmt") {
+        process::exit(fmt(env::args().skip(2)));
+    }
+
+    let cli = match parse_args(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("{message}\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    if cli.help {
+        println!("{USAGE}");
+        return;
+    }
+
+    let run = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(cli));
+    let status = run
+        .expect("failed to spawn interpreter thread")
+        .join()
+        .expect("interpreter thread panicked");
+    // Exiting skips flushing what the script printed
+    let _ = io::stdout().flush();
+    process::exit(status);
+}
+
+// Options come before the script, everything after it is left to the script
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut cli = Cli {
+        options: Options {
+            engine: Engine::Evaluator,
+            disassemble: false,
+            args: vec![],
+        },
+        script: None,
+        interactive: false,
+        help: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine=eval" => cli.options.engine = Engine::Evaluator,
+            "--engine=vm" => cli.options.engine = Engine::Vm,
+            "--disassemble" => cli.options.disassemble = true,
+            "-i" => cli.interactive = true,
+            "-h" | "--help" => cli.help = true,
+            "-e" => {
+                let code = args.next().ok_or_else(|| String::from("-e needs code"))?;
+                cli.script = Some(Script::Code(code));
+                break;
+            }
+            "-" => {
+                cli.script = Some(Script::Stdin);
+                break;
+            }
+            // Script whose name starts with a dash
+            "--" => {
+                cli.script = args.next().map(Script::File);
+                break;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown argument {arg}")),
+            _ => {
+                cli.script = Some(Script::File(arg));
+                break;
+            }
+        }
+    }
+    cli.options.args = args.collect();
+    Ok(cli)
+}
+
+// Run the script, then the repl when there is no script or -i asks for it.
+// Returns the exit status
+fn run(cli: Cli) -> i32 {
+    let Some(script) = cli.script else {
+        println!("---huytq intepreter---");
+        return repl(Repl::new(cli.options));
+    };
+
+    let mut interpreter = session(&cli.options);
+    let status = run_script(&mut interpreter, &cli.options, script);
+    if cli.interactive {
+        return repl(Repl::with_interpreter(cli.options, interpreter));
+    }
+    status
+}
+
+// Interpreter whose scripts get the arguments from args()
+fn session(options: &Options) -> Interpreter {
+    let mut interpreter = Interpreter::with_engine(options.engine);
+    let args = options.args.clone();
+    interpreter.register_fn("args", move |call_args: &[Object]| {
+        if !call_args.is_empty() {
+            return Err(RuntimeError::ArgumentCount {
+                expected: 0,
+                found: call_args.len(),
+            });
+        }
+        Ok(args.clone())
+    });
+    interpreter
+}
+
+fn run_script(interpreter: &mut Interpreter, options: &Options, script: Script) -> i32 {
+    let (name, source) = match script {
+        Script::File(path) => {
+            let source = fs::read_to_string(&path);
+            (path, source)
+        }
+        Script::Code(code) => (String::from("-e"), Ok(code)),
+        Script::Stdin => (String::from("-"), io::read_to_string(io::stdin())),
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Can not read {name}: {e}");
+            return EXIT_NO_INPUT;
+        }
+    };
+
+    if options.disassemble {
+        match interpreter.disassemble(&source) {
+            Ok(listing) => print!("{listing}"),
+            Err(e) => {
+                eprintln!("{}", e.render(&source));
+                return exit_status(&e);
+            }
+        }
+    }
+    match interpreter.eval_str(&source) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e.render(&source));
+            exit_status(&e)
+        }
+    }
+}
+
+fn exit_status(e: &InterpreterError) -> i32 {
+    match e {
+        InterpreterError::Runtime(..) => EXIT_RUNTIME_ERROR,
+        InterpreterError::Io { .. } => EXIT_NO_INPUT,
+        InterpreterError::Lex(..)
+        | InterpreterError::Parse(..)
+        | InterpreterError::Compile(..)
+        | InterpreterError::Resolve(..)
+        | InterpreterError::Syntax(_) => EXIT_SYNTAX_ERROR,
+    }
+}
+
+// Rewrite every file in canonical form, or with --check only list those that
+// are not. Returns the exit status
+fn fmt(args: impl Iterator<Item = String>) -> i32 {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown argument {arg}\n{USAGE}");
+                return 2;
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("no files to format\n{USAGE}");
+        return 2;
+    }
+
+    let mut status = 0;
+    for file in files {
+        let source = match fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{file}: {e}");
+                status = 1;
+                continue;
+            }
+        };
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(message) => {
+                eprintln!("{file}: {message}");
+                status = 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{file}");
+            status = 1;
+        } else if let Err(e) = fs::write(&file, formatted) {
+            eprintln!("{file}: {e}");
+            status = 1;
+        }
+    }
+    status
+}
+
+fn format_source(source: &str) -> Result<String, String> {
+    // The printer never sees the #! line the lexer skips, it is put back
+    // unchanged
+    let shebang = if source.starts_with("#!") {
+        source.split_inclusive('\n').next().unwrap_or(source)
+    } else {
+        ""
+    };
+    let formatted = Interpreter::format(source).map_err(|e| e.render(source))?;
+    let formatted = format!("{shebang}{formatted}");
+    // Never write output that means something else than the input
+    if !Interpreter::equivalent(source, &formatted) {
+        return Err(String::from("formatting would change the program"));
+    }
+    Ok(formatted)
+}
+
+// Repl line starting with a colon
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    // Tokens the lexer yields for the source
+    Tokens(&'a str),
+    // Source as the parser understood it
+    Ast(&'a str),
+    // Global variables of the session
+    Env,
+    // Run a script in the session
+    Load(&'a str),
+    // Start over with a fresh session
+    Reset,
+    Quit,
+}
+
+// None when line is source code rather than a command
+fn parse_command(line: &str) -> Option<Result<Command<'_>, String>> {
+    let line = line.trim().strip_prefix(':')?;
+    let (name, argument) = match line.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (line, ""),
+    };
+    let command = match (name, argument) {
+        ("tokens", source) => Ok(Command::Tokens(source)),
+        ("ast", source) => Ok(Command::Ast(source)),
+        ("env", "") => Ok(Command::Env),
+        ("load", "") => Err(String::from(":load needs a file")),
+        ("load", path) => Ok(Command::Load(path)),
+        ("reset", "") => Ok(Command::Reset),
+        ("quit", "") => Ok(Command::Quit),
+        ("env" | "reset" | "quit", _) => Err(format!(":{name} takes no argument")),
+        _ => Err(format!("unknown command :{name}, commands are {COMMANDS}")),
+    };
+    Some(command)
+}
+
+fn rc_file() -> Option<PathBuf> {
+    let path = home::home_dir()?.join(RC_FILE);
+    path.is_file().then_some(path)
+}
+
+// Word before the cursor, where it starts and the candidates it can be
+// completed with
+fn complete(names: &[String], before: &str) -> (usize, Vec<String>) {
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+        .last()
+        .map_or(before.len(), |(i, _)| i);
+    let word = &before[start..];
+    if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+        return (start, vec![]);
+    }
+    let candidates = names
+        .iter()
+        .filter(|name| name.starts_with(word))
+        .cloned()
+        .collect();
+    (start, candidates)
+}
+
+// Line editor support: completes keywords, builtins and global variables
+#[derive(Default)]
+struct ReplHelper {
+    // Sorted, refreshed before every line is read
+    names: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&self.names, &line[..pos]))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+struct Repl {
+    options: Options,
+    interpreter: Interpreter,
+}
+
+impl Repl {
+    fn new(options: Options) -> Self {
+        let interpreter = session(&options);
+        let mut repl = Self::with_interpreter(options, interpreter);
+        if let Some(path) = rc_file() {
+            repl.load(&path.display().to_string());
+        }
+        repl
+    }
+
+    // Session that goes on in interpreter, the rc file is not run
+    fn with_interpreter(options: Options, interpreter: Interpreter) -> Self {
+        Self {
+            options,
+            interpreter,
+        }
+    }
+
+    // Everything a word can be completed with
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Interpreter::keywords()
+            .into_iter()
+            .chain(self.interpreter.builtins())
+            .chain(self.interpreter.globals().into_iter().map(|(name, _)| name))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Run line, false once the repl should stop
+    fn line(&mut self, line: &str) -> bool {
+        match parse_command(line) {
+            None => Self::print(self.eval(line)),
+            Some(Err(message)) => println!("{message}"),
+            Some(Ok(command)) => return self.command(command),
+        }
+        true
+    }
+
+    fn command(&mut self, command: Command) -> bool {
+        match command {
+            Command::Tokens(source) => println!("{}", Interpreter::tokens(source)),
+            Command::Ast(source) => match Interpreter::format(source) {
+                Ok(formatted) => print!("{formatted}"),
+                Err(e) => println!("{}", e.render(source)),
+            },
+            Command::Env => {
+                for (name, value) in self.interpreter.globals() {
+                    println!("{name} = {}", value.repr());
+                }
+            }
+            Command::Load(path) => Self::print(self.load(path)),
+            Command::Reset => *self = Self::new(self.options.clone()),
+            Command::Quit => return false,
+        }
+        true
+    }
+
+    fn load(&mut self, path: &str) -> Option<Object> {
+        match fs::read_to_string(path) {
+            Ok(source) => self.eval(&source),
+            Err(e) => {
+                println!("Can not read {path}: {e}");
+                None
+            }
+        }
+    }
+
+    // Value of source, errors are printed
+    fn eval(&mut self, source: &str) -> Option<Object> {
+        if self.options.disassemble {
+            match self.interpreter.disassemble(source) {
+                Ok(listing) => print!("{listing}"),
+                Err(e) => println!("{}", e.render(source)),
+            }
+        }
+        match self.interpreter.eval_str(source) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                println!("{}", e.render(source));
+                None
+            }
+        }
+    }
+
+    fn print(value: Option<Object>) {
+        if let Some(v) = value {
+            println!("{}", v.repr());
+        }
+    }
+}
+
+// Read and run input until :quit or the end of input, returns the exit
+// status. The history is loaded from and saved to the home directory
+fn repl(mut repl: Repl) -> i32 {
+    let config = Config::builder()
+        .max_history_size(HISTORY_SIZE)
+        .and_then(|config| config.history_ignore_dups(true))
+        .map(|config| config.auto_add_history(false).build());
+    let mut editor: Editor<ReplHelper, FileHistory> = match config.and_then(Editor::with_config) {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+    editor.set_helper(Some(ReplHelper::default()));
+    let history = home::home_dir().map(|home| home.join(HISTORY_FILE));
+    if let Some(path) = &history {
+        // There is no history file before the first session
+        let _ = editor.load_history(path);
+    }
+
+    let status = read_eval(&mut repl, &mut editor);
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Can not save history to {}: {e}", path.display());
+        }
+    }
+    status
+}
+
+// Lines are collected until they form complete input, an empty line runs what
+// there is so far anyway. Ctrl-C drops the input so far
+fn read_eval(repl: &mut Repl, editor: &mut Editor<ReplHelper, FileHistory>) -> i32 {
+    let mut input = String::new();
     loop {
-        let mut input = String::new();
-        match io::stdin().read_line(&mut input) {
-            Err(e) => println!("{e}"),
-            Ok(_) => {
-                let lexer = Lexer::new(&input);
-                let mut parser = Parser::new(lexer);
-                let program = match parser.parse_program() {
-                    Err(e) => {
-                        println!("{:?}", e);
-                        Program { statements: vec![] }
-                    }
-                    Ok(program) => program,
-                };
-                match evaluator.eval(program, env.clone()) {
-                    Ok(v) => println!("{:?}", v),
-                    Err(e) => println!("{:?}", e),
+        if let Some(helper) = editor.helper_mut() {
+            helper.names = repl.names();
+        }
+        let prompt = if input.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                println!();
+                if !input.trim().is_empty() {
+                    repl.line(&input);
                 }
+                return 0;
             }
+            Err(e) => {
+                eprintln!("{e}");
+                return 1;
+            }
+        };
+
+        if input.is_empty() && parse_command(&line).is_some() {
+            let _ = editor.add_history_entry(line.as_str());
+            if !repl.line(&line) {
+                return 0;
+            }
+            continue;
+        }
+        let blank = line.trim().is_empty();
+        input.push_str(&line);
+        input.push('\n');
+        if input.trim().is_empty() {
+            input.clear();
+        } else if blank || !Interpreter::is_incomplete(&input) {
+            // Recalling the entry brings back all its lines
+            let _ = editor.add_history_entry(input.trim_end());
+            repl.line(&input);
+            input.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let testcases = vec![
+            ("1 + 2", None),
+            (
+                ":tokens let a = 1\n",
+                Some(Ok(Command::Tokens("let a = 1"))),
+            ),
+            (":ast", Some(Ok(Command::Ast("")))),
+            ("  :env  ", Some(Ok(Command::Env))),
+            (
+                ":load  scripts/a.mk",
+                Some(Ok(Command::Load("scripts/a.mk"))),
+            ),
+            (":reset", Some(Ok(Command::Reset))),
+            (":quit\n", Some(Ok(Command::Quit))),
+            (":load", Some(Err(String::from(":load needs a file")))),
+            (
+                ":quit now",
+                Some(Err(String::from(":quit takes no argument"))),
+            ),
+        ];
+        for (line, expected) in testcases.into_iter() {
+            assert_eq!(parse_command(line), expected, "{line}");
+        }
+        assert!(matches!(parse_command(":help"), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        fn cli(script: Option<Script>, args: &[&str]) -> Cli {
+            Cli {
+                options: Options {
+                    engine: Engine::Evaluator,
+                    disassemble: false,
+                    args: args.iter().map(|arg| arg.to_string()).collect(),
+                },
+                script,
+                interactive: false,
+                help: false,
+            }
+        }
+        let file = |name: &str| Some(Script::File(String::from(name)));
+        let testcases = vec![
+            (vec![], Ok(cli(None, &[]))),
+            (vec!["a.mk"], Ok(cli(file("a.mk"), &[]))),
+            (vec!["a.mk", "-i", "x"], Ok(cli(file("a.mk"), &["-i", "x"]))),
+            (
+                vec!["-e", "print(1)", "x"],
+                Ok(cli(Some(Script::Code(String::from("print(1)"))), &["x"])),
+            ),
+            (vec!["-", "x"], Ok(cli(Some(Script::Stdin), &["x"]))),
+            (vec!["--", "-a.mk"], Ok(cli(file("-a.mk"), &[]))),
+            (
+                vec!["-i", "--engine=vm", "--disassemble", "a.mk"],
+                Ok(Cli {
+                    options: Options {
+                        engine: Engine::Vm,
+                        disassemble: true,
+                        args: vec![],
+                    },
+                    interactive: true,
+                    ..cli(file("a.mk"), &[])
+                }),
+            ),
+            (
+                vec!["--help"],
+                Ok(Cli {
+                    help: true,
+                    ..cli(None, &[])
+                }),
+            ),
+            (vec!["-e"], Err(String::from("-e needs code"))),
+            (vec!["-x"], Err(String::from("unknown argument -x"))),
+        ];
+        for (args, expected) in testcases.into_iter() {
+            let args = args.into_iter().map(String::from);
+            assert_eq!(parse_args(args), expected);
+        }
+    }
+
+    #[test]
+    fn test_complete() {
+        let names: Vec<String> = ["counter", "len", "let", "while"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let testcases = vec![
+            ("le", (0, vec!["len", "let"])),
+            ("1 + cou", (4, vec!["counter"])),
+            ("f(wh", (2, vec!["while"])),
+            ("x.é_le", (2, vec![])),
+            ("1 + ", (4, vec![])),
+            ("12", (0, vec![])),
+            ("", (0, vec![])),
+        ];
+        for (before, (start, candidates)) in testcases.into_iter() {
+            assert_eq!(
+                complete(&names, before),
+                (start, candidates.iter().map(|c| c.to_string()).collect()),
+                "{before}"
+            );
         }
     }
 }
@@ -1,84 +1,1056 @@
+use crate::builtin::{self, Builtin};
+use crate::error::RuntimeError;
+use crate::span::Spanned;
+use crate::statement::Statement;
+use crate::token;
+use crate::vm::Closure;
+
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
 use std::ops;
 use std::rc::Rc;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Object {
+    Integer(i64),
     Number(f64),
     String(String),
+    Boolean(bool),
+    // Arrays are shared by reference, so a[0] = 1 is seen through every alias
+    Array(Rc<RefCell<Vec<Object>>>),
+    Hash(Rc<RefCell<Hash>>),
+    Function(Rc<Function>),
+    // Function compiled for the vm, see vm::Closure
+    Closure(Rc<Closure>),
+    Builtin(Rc<Builtin>),
     Null,
-    Return(Box<Object>),
+}
+
+impl Object {
+    // Truthiness used by `if` and `!`: false, null, 0, NaN and empty strings, arrays
+    // and hashes are falsy, every other value (including functions) is truthy
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::Boolean(b) => *b,
+            Self::Integer(num) => *num != 0,
+            Self::Number(num) => *num != 0.0 && !num.is_nan(),
+            Self::String(s) => !s.is_empty(),
+            Self::Array(elements) => !elements.borrow().is_empty(),
+            Self::Hash(hash) => !hash.borrow().is_empty(),
+            Self::Null => false,
+            Self::Function(_) | Self::Closure(_) | Self::Builtin(_) => true,
+        }
+    }
+
+    // Value equality used by == and !=. Unlike PartialEq, an integer and a float
+    // holding the same value are equal, including inside arrays and hashes
+    pub fn equals(&self, rhs: &Object) -> bool {
+        self.eq_in(rhs, false, &mut vec![])
+    }
+
+    // seen holds the pairs of arrays and hashes being compared, meeting a pair
+    // again means both sides loop back the same way so it counts as equal
+    fn eq_in(&self, rhs: &Object, exact: bool, seen: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, rhs) {
+            (Self::Integer(num1), Self::Integer(num2)) => num1 == num2,
+            (Self::Number(num1), Self::Number(num2)) => num1 == num2,
+            (Self::Integer(num1), Self::Number(num2))
+            | (Self::Number(num2), Self::Integer(num1)) => !exact && *num1 as f64 == *num2,
+            (Self::String(s1), Self::String(s2)) => s1 == s2,
+            (Self::Boolean(b1), Self::Boolean(b2)) => b1 == b2,
+            (Self::Array(elements1), Self::Array(elements2)) => {
+                let pair = (
+                    Rc::as_ptr(elements1) as *const (),
+                    Rc::as_ptr(elements2) as *const (),
+                );
+                if seen.contains(&pair) {
+                    return true;
+                }
+                seen.push(pair);
+                let (elements1, elements2) = (elements1.borrow(), elements2.borrow());
+                let equal = elements1.len() == elements2.len()
+                    && elements1
+                        .iter()
+                        .zip(elements2.iter())
+                        .all(|(v1, v2)| v1.eq_in(v2, exact, seen));
+                seen.pop();
+                equal
+            }
+            (Self::Hash(hash1), Self::Hash(hash2)) => {
+                let pair = (
+                    Rc::as_ptr(hash1) as *const (),
+                    Rc::as_ptr(hash2) as *const (),
+                );
+                if seen.contains(&pair) {
+                    return true;
+                }
+                seen.push(pair);
+                let equal = hash1.borrow().eq_in(&hash2.borrow(), exact, seen);
+                seen.pop();
+                equal
+            }
+            (Self::Function(function1), Self::Function(function2)) => function1 == function2,
+            (Self::Closure(closure1), Self::Closure(closure2)) => closure1 == closure2,
+            (Self::Builtin(builtin1), Self::Builtin(builtin2)) => builtin1 == builtin2,
+            (Self::Null, Self::Null) => true,
+            _ => false,
+        }
+    }
+
+    // Ordering for <, <=, > and >=. Only numbers and strings can be ordered,
+    // None is returned when a NaN is involved so every comparison is false
+    pub fn compare(&self, rhs: &Object) -> Result<Option<Ordering>, RuntimeError> {
+        match (self, rhs) {
+            (Self::Integer(num1), Self::Integer(num2)) => Ok(Some(num1.cmp(num2))),
+            (Self::Integer(num1), Self::Number(num2)) => Ok((*num1 as f64).partial_cmp(num2)),
+            (Self::Number(num1), Self::Integer(num2)) => Ok(num1.partial_cmp(&(*num2 as f64))),
+            (Self::Number(num1), Self::Number(num2)) => Ok(num1.partial_cmp(num2)),
+            (Self::String(s1), Self::String(s2)) => Ok(Some(s1.cmp(s2))),
+            _ => Err(RuntimeError::NotComparable(self.clone(), rhs.clone())),
+        }
+    }
+
+    // Name reported by the type builtin
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Integer(_) => "integer",
+            Self::Number(_) => "float",
+            Self::String(_) => "string",
+            Self::Boolean(_) => "boolean",
+            Self::Array(_) => "array",
+            Self::Hash(_) => "hash",
+            Self::Function(_) | Self::Closure(_) => "function",
+            Self::Builtin(_) => "builtin",
+            Self::Null => "null",
+        }
+    }
+
+    pub fn new_array(elements: Vec<Object>) -> Object {
+        Self::Array(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn new_hash(hash: Hash) -> Object {
+        Self::Hash(Rc::new(RefCell::new(hash)))
+    }
+
+    // Key used to store the object in a hash, only numbers, strings and booleans
+    // can be keys since every other object is either mutable or compared by identity
+    pub fn hash_key(&self) -> Result<HashKey, RuntimeError> {
+        match self {
+            Self::Integer(num) => Ok(HashKey::Integer(*num)),
+            Self::Number(num) if num.is_nan() => Err(RuntimeError::NanHashKey),
+            // Floats equal to an integer (including -0.0) share the integer's key
+            Self::Number(num)
+                if num.fract() == 0.0 && *num >= i64::MIN as f64 && *num < i64::MAX as f64 =>
+            {
+                Ok(HashKey::Integer(*num as i64))
+            }
+            Self::Number(num) => Ok(HashKey::Number(num.to_bits())),
+            Self::String(s) => Ok(HashKey::String(s.clone())),
+            Self::Boolean(b) => Ok(HashKey::Boolean(*b)),
+            _ => Err(RuntimeError::UnhashableKey(self.clone())),
+        }
+    }
+
+    // Missing hash keys give null rather than an error
+    pub fn index(&self, index: &Object) -> Result<Object, RuntimeError> {
+        match self {
+            Self::Hash(hash) => Ok(hash.borrow().get(index)?.unwrap_or(Self::Null)),
+            Self::Array(elements) => {
+                let elements = elements.borrow();
+                let i = Self::resolve_index(index, elements.len())?;
+                Ok(elements[i].clone())
+            }
+            Self::String(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                let i = Self::resolve_index(index, chars.len())?;
+                Ok(Self::String(chars[i].to_string()))
+            }
+            _ => Err(RuntimeError::NotIndexable(self.clone())),
+        }
+    }
+
+    pub fn set_index(&self, index: &Object, value: Object) -> Result<(), RuntimeError> {
+        match self {
+            Self::Array(elements) => {
+                let mut elements = elements.borrow_mut();
+                let i = Self::resolve_index(index, elements.len())?;
+                elements[i] = value;
+                Ok(())
+            }
+            Self::Hash(hash) => hash.borrow_mut().insert(index.clone(), value),
+            _ => Err(RuntimeError::NotIndexAssignable(self.clone())),
+        }
+    }
+
+    // Slicing follows Python: negative bounds count from the end and bounds past
+    // either end are clamped, so it never fails on a valid container
+    pub fn slice(
+        &self,
+        start: Option<&Object>,
+        end: Option<&Object>,
+    ) -> Result<Object, RuntimeError> {
+        let len = match self {
+            Self::Array(elements) => elements.borrow().len(),
+            Self::String(s) => s.chars().count(),
+            _ => return Err(RuntimeError::NotSliceable(self.clone())),
+        };
+        let start = match start {
+            None => 0,
+            Some(start) => Self::clamp_index(start, len)?,
+        };
+        let end = match end {
+            None => len,
+            Some(end) => Self::clamp_index(end, len)?.max(start),
+        };
+
+        match self {
+            Self::Array(elements) => Ok(Self::new_array(elements.borrow()[start..end].to_vec())),
+            Self::String(s) => Ok(Self::String(
+                s.chars().skip(start).take(end - start).collect(),
+            )),
+            _ => unreachable!(),
+        }
+    }
+
+    // Values a for loop visits: array elements, the characters of a string or
+    // the keys of a hash. They are copied up front so the loop body can modify
+    // the container it iterates over
+    pub fn iter_values(&self) -> Result<Vec<Object>, RuntimeError> {
+        match self {
+            Self::Array(elements) => Ok(elements.borrow().clone()),
+            Self::String(s) => Ok(s.chars().map(|c| Self::String(c.to_string())).collect()),
+            Self::Hash(hash) => Ok(hash.borrow().iter().map(|pair| pair.key.clone()).collect()),
+            _ => Err(RuntimeError::NotIterable(self.clone())),
+        }
+    }
+
+    // Turn an index object into a position inside a container of length len,
+    // negative indices count from the end
+    fn resolve_index(index: &Object, len: usize) -> Result<usize, RuntimeError> {
+        let i = Self::integer_index(index)?;
+        let resolved = if i < 0 { i + len as i64 } else { i };
+        if resolved < 0 || resolved >= len as i64 {
+            return Err(RuntimeError::IndexOutOfRange {
+                index: i,
+                length: len,
+            });
+        }
+        Ok(resolved as usize)
+    }
+
+    fn clamp_index(index: &Object, len: usize) -> Result<usize, RuntimeError> {
+        let i = Self::integer_index(index)?;
+        let resolved = if i < 0 { i + len as i64 } else { i };
+        Ok(resolved.clamp(0, len as i64) as usize)
+    }
+
+    fn integer_index(index: &Object) -> Result<i64, RuntimeError> {
+        match index {
+            Self::Integer(num) => Ok(*num),
+            _ => Err(RuntimeError::InvalidIndex(index.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    Number(u64),
+    String(String),
+    Boolean(bool),
+}
+
+#[derive(Debug, Clone)]
+pub struct HashPair {
+    pub key: Object,
+    pub value: Object,
+}
+
+// Hash map from hashable objects to objects that remembers insertion order, so
+// printing and iterating a hash is deterministic
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    pairs: Vec<HashPair>,
+    positions: HashMap<HashKey, usize>,
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: Object, value: Object) -> Result<(), RuntimeError> {
+        match self.positions.get(&key.hash_key()?) {
+            Some(i) => self.pairs[*i].value = value,
+            None => {
+                self.positions.insert(key.hash_key()?, self.pairs.len());
+                self.pairs.push(HashPair { key, value });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &Object) -> Result<Option<Object>, RuntimeError> {
+        Ok(self
+            .positions
+            .get(&key.hash_key()?)
+            .map(|i| self.pairs[*i].value.clone()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    // Pairs in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = &HashPair> {
+        self.pairs.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // Two hashes are equal when they hold the same keys and values, in any order
+    fn eq_in(&self, other: &Self, exact: bool, seen: &mut Vec<(*const (), *const ())>) -> bool {
+        self.len() == other.len()
+            && self.pairs.iter().all(|pair| match other.get(&pair.key) {
+                Ok(Some(value)) => pair.value.eq_in(&value, exact, seen),
+                _ => false,
+            })
+    }
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_in(other, true, &mut vec![])
+    }
+}
+
+// Strict equality: unlike Object::equals, 1 and 1.0 are different objects
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_in(other, true, &mut vec![])
+    }
+}
+
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Vec<Spanned<Statement>>,
+    // Environment the function was defined in, shared rather than copied so
+    // the closure sees (and can change) later updates to captured variables
+    pub env: Rc<RefCell<Environment>>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The captured environment usually contains the function itself,
+        // so it is left out to avoid recursing forever
+        f.debug_struct("Function")
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Text written by print and returned by str, strings are not quoted
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, false, &mut vec![])
+    }
+}
+
+// Form shown by the repl and returned by repr, see Object::repr
+pub struct Repr<'a>(&'a Object);
+
+impl fmt::Display for Repr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write(f, true, &mut vec![])
+    }
+}
+
+impl Object {
+    // Like Display, but strings are quoted and escaped at every level, so
+    // "1" and 1 or ["a, b"] and ["a", "b"] can be told apart
+    pub fn repr(&self) -> Repr<'_> {
+        Repr(self)
+    }
+
+    // seen holds the arrays and hashes being written, one that contains itself
+    // is written as [...] or {...} the second time instead of recursing forever
+    fn write(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        quote: bool,
+        seen: &mut Vec<*const ()>,
+    ) -> fmt::Result {
+        match self {
+            Self::Integer(num) => write!(f, "{}", num),
+            Self::Number(num) => write!(f, "{}", token::format_number(*num)),
+            Self::String(s) if quote => write!(f, "{}", token::string_literal(s)),
+            Self::String(s) => write!(f, "{}", s),
+            Self::Boolean(b) => write!(f, "{}", b),
+            Self::Array(elements) => {
+                let ptr = Rc::as_ptr(elements) as *const ();
+                if seen.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+                seen.push(ptr);
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write(f, quote, seen)?;
+                }
+                seen.pop();
+                write!(f, "]")
+            }
+            Self::Hash(hash) => {
+                let ptr = Rc::as_ptr(hash) as *const ();
+                if seen.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+                seen.push(ptr);
+                write!(f, "{{")?;
+                for (i, pair) in hash.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    pair.key.write(f, quote, seen)?;
+                    write!(f, ": ")?;
+                    pair.value.write(f, quote, seen)?;
+                }
+                seen.pop();
+                write!(f, "}}")
+            }
+            Self::Function(function) => write!(f, "fn({})", function.params.join(", ")),
+            Self::Closure(closure) => write!(f, "fn({})", closure.function.params.join(", ")),
+            Self::Builtin(builtin) => write!(f, "builtin {}", builtin.name),
+            Self::Null => write!(f, "null"),
+        }
+    }
+}
+
+// Operands of an arithmetic operator after promotion: two integers stay exact,
+// an integer mixed with a float is converted to a float
+enum Numbers {
+    Integers(i64, i64),
+    Floats(f64, f64),
 }
 
 impl ops::Add for Object {
-    type Output = Result<Object, String>;
-
-    fn add(self, rhs: Self) -> Result<Object, String> {
-        return match self {
-            Self::Number(num1) => match rhs {
-                Self::Number(num2) => Ok(Self::Number(num1 + num2)),
-                Self::String(s2) => Ok(Self::String(format!("{}{}", num1, s2))),
-                Self::Null | Self::Return(_) => return Err(format!("Invalid value rhs = NULL")),
-            },
-            Self::String(s1) => match rhs {
-                Self::Number(num2) => Ok(Self::String(format!("{}{}", s1, num2))),
-                Self::String(s2) => Ok(Self::String(s1 + &s2)),
-                Self::Null | Self::Return(_) => return Err(format!("Invalid value rhs = NULL")),
+    type Output = Result<Object, RuntimeError>;
+
+    fn add(self, rhs: Self) -> Result<Object, RuntimeError> {
+        match (self, rhs) {
+            (Self::String(s1), Self::String(s2)) => Ok(Self::String(s1 + &s2)),
+            (Self::String(s1), rhs @ (Self::Integer(_) | Self::Number(_))) => {
+                Ok(Self::String(format!("{}{}", s1, rhs.number_to_string())))
+            }
+            (lhs @ (Self::Integer(_) | Self::Number(_)), Self::String(s2)) => {
+                Ok(Self::String(format!("{}{}", lhs.number_to_string(), s2)))
+            }
+            (lhs, rhs) => match lhs.numeric_operands(rhs, "+")? {
+                Numbers::Integers(num1, num2) => {
+                    Self::checked(num1.checked_add(num2), || format!("{} + {}", num1, num2))
+                }
+                Numbers::Floats(num1, num2) => Ok(Self::Number(num1 + num2)),
             },
-            Self::Null | Self::Return(_) => return Err(format!("Invalud value lhs = NULL")),
-        };
+        }
     }
 }
 
 impl ops::Sub for Object {
-    type Output = Result<Object, String>;
+    type Output = Result<Object, RuntimeError>;
 
-    fn sub(self, rhs: Self) -> Result<Object, String> {
-        return match self {
-            Self::Number(num1) => match rhs {
-                Self::Number(num2) => Ok(Self::Number(num1 - num2)),
-                _ => return Err(format!("Invalid value rhs = {:?}", self)),
-            },
-            _ => return Err(format!("Invalud value lhs = {:?}", self)),
+    fn sub(self, rhs: Self) -> Result<Object, RuntimeError> {
+        match self.numeric_operands(rhs, "-")? {
+            Numbers::Integers(num1, num2) => {
+                Self::checked(num1.checked_sub(num2), || format!("{} - {}", num1, num2))
+            }
+            Numbers::Floats(num1, num2) => Ok(Self::Number(num1 - num2)),
+        }
+    }
+}
+
+impl ops::Mul for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    fn mul(self, rhs: Self) -> Result<Object, RuntimeError> {
+        match self.numeric_operands(rhs, "*")? {
+            Numbers::Integers(num1, num2) => {
+                Self::checked(num1.checked_mul(num2), || format!("{} * {}", num1, num2))
+            }
+            Numbers::Floats(num1, num2) => Ok(Self::Number(num1 * num2)),
+        }
+    }
+}
+
+impl ops::Div for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    // True division, the result is always a float
+    fn div(self, rhs: Self) -> Result<Object, RuntimeError> {
+        let (num1, num2) = match self.numeric_operands(rhs, "/")? {
+            Numbers::Integers(num1, num2) => (num1 as f64, num2 as f64),
+            Numbers::Floats(num1, num2) => (num1, num2),
         };
+        if num2 == 0.0 {
+            return Err(RuntimeError::DivisionByZero);
+        }
+        Ok(Self::Number(num1 / num2))
+    }
+}
+
+impl ops::Rem for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    // Floored modulo: the result takes the sign of the divisor, so that
+    // a == (a // b) * b + a % b holds
+    fn rem(self, rhs: Self) -> Result<Object, RuntimeError> {
+        match self.numeric_operands(rhs, "%")? {
+            Numbers::Integers(_, 0) => Err(RuntimeError::DivisionByZero),
+            Numbers::Integers(num1, num2) => {
+                let remainder =
+                    Self::checked(num1.checked_rem(num2), || format!("{} % {}", num1, num2))?;
+                match remainder {
+                    Self::Integer(r) if r != 0 && (r < 0) != (num2 < 0) => {
+                        Ok(Self::Integer(r + num2))
+                    }
+                    _ => Ok(remainder),
+                }
+            }
+            Numbers::Floats(_, 0.0) => Err(RuntimeError::DivisionByZero),
+            Numbers::Floats(num1, num2) => {
+                let mut remainder = num1 % num2;
+                if remainder != 0.0 && (remainder < 0.0) != (num2 < 0.0) {
+                    remainder += num2;
+                }
+                Ok(Self::Number(remainder))
+            }
+        }
+    }
+}
+
+impl ops::BitAnd for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    fn bitand(self, rhs: Self) -> Result<Object, RuntimeError> {
+        let (num1, num2) = self.integer_operands(rhs, "&")?;
+        Ok(Self::Integer(num1 & num2))
+    }
+}
+
+impl ops::BitOr for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    fn bitor(self, rhs: Self) -> Result<Object, RuntimeError> {
+        let (num1, num2) = self.integer_operands(rhs, "|")?;
+        Ok(Self::Integer(num1 | num2))
+    }
+}
+
+impl ops::BitXor for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    fn bitxor(self, rhs: Self) -> Result<Object, RuntimeError> {
+        let (num1, num2) = self.integer_operands(rhs, "^")?;
+        Ok(Self::Integer(num1 ^ num2))
+    }
+}
+
+impl ops::Shl for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    // Shifting a bit out of the integer (or into the sign bit) is an overflow
+    fn shl(self, rhs: Self) -> Result<Object, RuntimeError> {
+        let (num1, num2) = self.integer_operands(rhs, "<<")?;
+        let amount = Self::shift_amount(num2)?;
+        let shifted = num1 << amount;
+        if shifted >> amount != num1 {
+            return Err(RuntimeError::IntegerOverflow(format!(
+                "{} << {}",
+                num1, num2
+            )));
+        }
+        Ok(Self::Integer(shifted))
+    }
+}
+
+impl ops::Shr for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    // Arithmetic shift, the sign is kept
+    fn shr(self, rhs: Self) -> Result<Object, RuntimeError> {
+        let (num1, num2) = self.integer_operands(rhs, ">>")?;
+        Ok(Self::Integer(num1 >> Self::shift_amount(num2)?))
+    }
+}
+
+impl ops::Neg for Object {
+    type Output = Result<Object, RuntimeError>;
+
+    fn neg(self) -> Result<Object, RuntimeError> {
+        match self {
+            Self::Integer(num) => Self::checked(num.checked_neg(), || format!("-{}", num)),
+            Self::Number(num) => Ok(Self::Number(-num)),
+            value => Err(RuntimeError::InvalidOperand {
+                operator: "-",
+                value,
+            }),
+        }
+    }
+}
+
+impl Object {
+    // An integer raised to a non negative integer stays an integer, any other
+    // combination gives a float
+    pub fn pow(self, rhs: Self) -> Result<Object, RuntimeError> {
+        match self.numeric_operands(rhs, "**")? {
+            Numbers::Integers(num1, num2) if num2 >= 0 => {
+                let exponent = u32::try_from(num2).ok();
+                Self::checked(exponent.and_then(|e| num1.checked_pow(e)), || {
+                    format!("{} ** {}", num1, num2)
+                })
+            }
+            Numbers::Integers(num1, num2) => Ok(Self::Number((num1 as f64).powf(num2 as f64))),
+            Numbers::Floats(num1, num2) => Ok(Self::Number(num1.powf(num2))),
+        }
+    }
+
+    pub fn floor_div(self, rhs: Self) -> Result<Object, RuntimeError> {
+        match self.numeric_operands(rhs, "//")? {
+            Numbers::Integers(_, 0) => Err(RuntimeError::DivisionByZero),
+            Numbers::Integers(num1, num2) => {
+                let quotient =
+                    Self::checked(num1.checked_div(num2), || format!("{} // {}", num1, num2))?;
+                match quotient {
+                    Self::Integer(q) if num1 % num2 != 0 && (num1 < 0) != (num2 < 0) => {
+                        Ok(Self::Integer(q - 1))
+                    }
+                    _ => Ok(quotient),
+                }
+            }
+            Numbers::Floats(_, 0.0) => Err(RuntimeError::DivisionByZero),
+            Numbers::Floats(num1, num2) => Ok(Self::Number((num1 / num2).floor())),
+        }
+    }
+
+    pub fn bit_not(self) -> Result<Object, RuntimeError> {
+        match self {
+            Self::Integer(num) => Ok(Self::Integer(!num)),
+            value => Err(RuntimeError::InvalidOperand {
+                operator: "~",
+                value,
+            }),
+        }
+    }
+
+    fn numeric_operands(self, rhs: Self, operator: &'static str) -> Result<Numbers, RuntimeError> {
+        match (self, rhs) {
+            (Self::Integer(num1), Self::Integer(num2)) => Ok(Numbers::Integers(num1, num2)),
+            (Self::Integer(num1), Self::Number(num2)) => Ok(Numbers::Floats(num1 as f64, num2)),
+            (Self::Number(num1), Self::Integer(num2)) => Ok(Numbers::Floats(num1, num2 as f64)),
+            (Self::Number(num1), Self::Number(num2)) => Ok(Numbers::Floats(num1, num2)),
+            (left, right) => Err(RuntimeError::TypeMismatch {
+                operator,
+                left,
+                right,
+            }),
+        }
+    }
+
+    fn integer_operands(
+        self,
+        rhs: Self,
+        operator: &'static str,
+    ) -> Result<(i64, i64), RuntimeError> {
+        match (self, rhs) {
+            (Self::Integer(num1), Self::Integer(num2)) => Ok((num1, num2)),
+            (left, right) => Err(RuntimeError::TypeMismatch {
+                operator,
+                left,
+                right,
+            }),
+        }
+    }
+
+    fn shift_amount(amount: i64) -> Result<u32, RuntimeError> {
+        match u32::try_from(amount) {
+            Ok(amount) if amount < i64::BITS => Ok(amount),
+            _ => Err(RuntimeError::ShiftOutOfRange(amount)),
+        }
+    }
+
+    fn checked(
+        result: Option<i64>,
+        expression: impl FnOnce() -> String,
+    ) -> Result<Object, RuntimeError> {
+        match result {
+            Some(num) => Ok(Self::Integer(num)),
+            None => Err(RuntimeError::IntegerOverflow(expression())),
+        }
+    }
+
+    fn number_to_string(&self) -> String {
+        match self {
+            Self::Integer(num) => num.to_string(),
+            Self::Number(num) => token::format_number(*num),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Global variables, numbered in the order their names are first met. The
+// resolver numbers the globals a program uses, so both engines read and write
+// them by index. A name can have a number before the variable is defined
+#[derive(Default, Clone)]
+pub struct Globals {
+    indices: HashMap<String, usize>,
+    names: Vec<String>,
+    // None until the variable is defined
+    values: Vec<Option<Object>>,
+}
+
+impl Globals {
+    // Number of name, a new one the first time
+    pub fn index(&mut self, name: &str) -> usize {
+        if let Some(index) = self.indices.get(name) {
+            return *index;
+        }
+        self.indices.insert(name.to_string(), self.names.len());
+        self.names.push(name.to_string());
+        self.values.push(None);
+        self.names.len() - 1
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Object> {
+        self.indices
+            .get(name)
+            .and_then(|index| self.values[*index].as_ref())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Object> {
+        let index = *self.indices.get(name)?;
+        self.values[index].as_mut()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Define or overwrite the variable numbered index
+    pub fn set(&mut self, index: usize, value: Object) {
+        self.values[index] = Some(value);
+    }
+
+    // Define or overwrite the variable
+    pub fn insert(&mut self, name: String, value: Object) {
+        let index = self.index(&name);
+        self.set(index, value);
+    }
+
+    // Defined variables, in the order they were numbered
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Object)> {
+        self.names
+            .iter()
+            .zip(self.values.iter())
+            .filter_map(|(name, value)| Some((name, value.as_ref()?)))
     }
 }
 
 pub struct Environment {
-    pub variables: HashMap<String, Object>,
+    // Variables of the global environment
+    pub variables: Globals,
+    // Variables of a function or block scope, numbered by the resolver in
+    // the order they are declared
+    pub slots: Vec<Object>,
+    // Only the global environment has builtins, they are looked up after
+    // every script variable
+    pub builtins: HashMap<String, Rc<Builtin>>,
     pub outer: Option<Rc<RefCell<Environment>>>,
-    pub in_function: bool,
+    // Global environment the scope is nested in, None for the global
+    // environment itself
+    global: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
     pub fn new(outer_option: Option<Rc<RefCell<Environment>>>) -> Rc<RefCell<Self>> {
         Rc::new(RefCell::new(match outer_option {
             None => Self {
-                variables: HashMap::new(),
+                variables: Globals::default(),
+                slots: vec![],
+                builtins: builtin::registry(),
                 outer: None,
-                in_function: false,
-            },
-            Some(outer_env) => Self {
-                variables: HashMap::new(),
-                outer: Some(outer_env),
-                in_function: false,
+                global: None,
             },
+            Some(outer_env) => {
+                let global = match &outer_env.borrow().global {
+                    Some(global) => global.clone(),
+                    None => outer_env.clone(),
+                };
+                Self {
+                    variables: Globals::default(),
+                    slots: vec![],
+                    builtins: HashMap::new(),
+                    outer: Some(outer_env),
+                    global: Some(global),
+                }
+            }
         }))
     }
 
-    pub fn get(&self, variable_name: &str) -> Result<Object, String> {
-        if self.variables.contains_key(variable_name) {
-            return Ok(self.variables.get(variable_name).unwrap().to_owned());
+    // Local variable in slot of the scope depth scopes out from this one
+    pub fn get_local(&self, depth: usize, slot: usize) -> Object {
+        match depth {
+            0 => self.slots[slot].clone(),
+            _ => self.outer().borrow().get_local(depth - 1, slot),
+        }
+    }
+
+    pub fn set_local(&mut self, depth: usize, slot: usize, value: Object) {
+        match depth {
+            0 => self.slots[slot] = value,
+            _ => self.outer().borrow_mut().set_local(depth - 1, slot, value),
+        }
+    }
+
+    // Scopes the resolver counted through always exist
+    fn outer(&self) -> &Rc<RefCell<Environment>> {
+        self.outer.as_ref().expect("resolved scope")
+    }
+
+    // Global variable numbered index, or the builtin of that name
+    pub fn get_global(&self, index: usize) -> Result<Object, RuntimeError> {
+        if let Some(global) = &self.global {
+            return global.borrow().get_global(index);
         }
+        if let Some(obj) = &self.variables.values[index] {
+            return Ok(obj.clone());
+        }
+        let name = self.variables.name(index);
+        match self.builtins.get(name) {
+            Some(builtin) => Ok(Object::Builtin(builtin.clone())),
+            None => Err(RuntimeError::UndefinedVariable(name.to_string())),
+        }
+    }
 
-        if let Some(outer_env) = &self.outer {
-            match outer_env.borrow().get(variable_name) {
-                Ok(obj) => return Ok(obj),
-                Err(e) => return Err(e),
+    pub fn set_global(&mut self, index: usize, value: Object) -> Result<(), RuntimeError> {
+        if let Some(global) = &self.global {
+            return global.borrow_mut().set_global(index, value);
+        }
+        match &mut self.variables.values[index] {
+            Some(obj) => {
+                *obj = value;
+                Ok(())
             }
+            None => Err(RuntimeError::UndeclaredAssignment(
+                self.variables.name(index).to_string(),
+            )),
         }
+    }
+
+    pub fn define_global(&mut self, index: usize, value: Object) -> Result<(), RuntimeError> {
+        match &mut self.variables.values[index] {
+            Some(_) => Err(RuntimeError::AlreadyDeclared(
+                self.variables.name(index).to_string(),
+            )),
+            slot => {
+                *slot = Some(value);
+                Ok(())
+            }
+        }
+    }
+
+    // Expose a Rust function to scripts under name. Arguments can be read with
+    // convert::arg, which checks they were passed and converts them with the
+    // TryFrom<&Object> conversions. The result is anything convertible
+    // into an object. The closure may capture (shared, interior mutable) host
+    // state. Register on the global environment so every scope sees it
+    pub fn register_fn<F, R, E>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&[Object]) -> Result<R, E> + 'static,
+        R: Into<Object>,
+        E: Into<RuntimeError>,
+    {
+        let func = move |args: &[Object]| func(args).map(Into::into).map_err(Into::into);
+        self.builtins.insert(
+            name.to_string(),
+            Rc::new(Builtin {
+                name: name.to_string(),
+                func: Rc::new(func),
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_truthy() {
+        let testcases = vec![
+            (Object::Boolean(false), false),
+            (Object::Boolean(true), true),
+            (Object::Integer(0), false),
+            (Object::Integer(-1), true),
+            (Object::Number(0.0), false),
+            (Object::Number(f64::NAN), false),
+            (Object::Number(-2.5), true),
+            (Object::String(String::new()), false),
+            (Object::String("a".to_string()), true),
+            (Object::Null, false),
+        ];
+
+        for (obj, expected) in testcases.into_iter() {
+            assert_eq!(obj.is_truthy(), expected, "{:?}", obj);
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let testcases = vec![
+            (
+                Object::Number(1.0),
+                Object::Number(2.0),
+                Ok(Some(Ordering::Less)),
+            ),
+            (
+                Object::Integer(3),
+                Object::Number(2.5),
+                Ok(Some(Ordering::Greater)),
+            ),
+            (Object::Number(f64::NAN), Object::Number(2.0), Ok(None)),
+            (
+                Object::String("b".to_string()),
+                Object::String("abc".to_string()),
+                Ok(Some(Ordering::Greater)),
+            ),
+            (
+                Object::String("a".to_string()),
+                Object::Number(1.0),
+                Err(RuntimeError::NotComparable(
+                    Object::String("a".to_string()),
+                    Object::Number(1.0),
+                )),
+            ),
+        ];
+
+        for (lhs, rhs, expected) in testcases.into_iter() {
+            assert_eq!(lhs.compare(&rhs), expected);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let mut hash = Hash::new();
+        hash.insert(Object::from("k"), Object::from("a\"b"))
+            .unwrap();
+        hash.insert(Object::Integer(1), Object::Number(0.5))
+            .unwrap();
+        let shared = Object::from(vec![1_i64]);
+        let testcases = vec![
+            (Object::Number(5.0), "5", "5"),
+            (Object::Number(-2.5), "-2.5", "-2.5"),
+            (Object::Number(1e21), "1e21", "1e21"),
+            (Object::Number(1.5e-7), "1.5e-7", "1.5e-7"),
+            (Object::Number(f64::INFINITY), "inf", "inf"),
+            (Object::from("a\nb"), "a\nb", "\"a\\nb\""),
+            (
+                Object::from(vec![Object::from("a, b"), Object::Null]),
+                "[a, b, null]",
+                "[\"a, b\", null]",
+            ),
+            (
+                Object::new_hash(hash),
+                "{k: a\"b, 1: 0.5}",
+                "{\"k\": \"a\\\"b\", 1: 0.5}",
+            ),
+            // The same array twice is not a cycle
+            (
+                Object::from(vec![shared.clone(), shared]),
+                "[[1], [1]]",
+                "[[1], [1]]",
+            ),
+        ];
+
+        for (obj, display, repr) in testcases.into_iter() {
+            assert_eq!(obj.to_string(), display);
+            assert_eq!(obj.repr().to_string(), repr);
+        }
+    }
+
+    #[test]
+    fn test_display_cycles() {
+        let array = Object::from(vec![1_i64]);
+        let hash = Object::new_hash(Hash::new());
+        if let (Object::Array(elements), Object::Hash(pairs)) = (&array, &hash) {
+            elements.borrow_mut().push(array.clone());
+            elements.borrow_mut().push(hash.clone());
+            pairs
+                .borrow_mut()
+                .insert(Object::from("self"), hash.clone())
+                .unwrap();
+            pairs
+                .borrow_mut()
+                .insert(Object::from("array"), array.clone())
+                .unwrap();
+        }
+
+        assert_eq!(array.to_string(), "[1, [...], {self: {...}, array: [...]}]");
+        assert_eq!(
+            hash.repr().to_string(),
+            "{\"self\": {...}, \"array\": [1, [...], {...}]}"
+        );
+    }
+
+    #[test]
+    fn test_equals_cycles() {
+        let (array1, array2) = (Object::from(vec![1_i64]), Object::from(vec![1_i64]));
+        for array in [&array1, &array2] {
+            if let Object::Array(elements) = array {
+                elements.borrow_mut().push(array.clone());
+            }
+        }
+
+        assert!(array1.equals(&array1));
+        assert!(array1.equals(&array2));
+        assert_eq!(array1, array2);
+        assert!(!array1.equals(&Object::from(vec![1_i64])));
+    }
+
+    #[test]
+    fn test_global_from_nested_scope() {
+        let global = Environment::new(None);
+        let index = global.borrow_mut().variables.index("g");
+        let inner = Environment::new(Some(Environment::new(Some(global.clone()))));
+        assert_eq!(
+            inner.borrow().get_global(index),
+            Err(RuntimeError::UndefinedVariable("g".to_string()))
+        );
 
-        return Err(format!("undefined variable {:?}", variable_name));
+        global.borrow_mut().variables.set(index, Object::Integer(1));
+        inner
+            .borrow_mut()
+            .set_global(index, Object::Integer(2))
+            .unwrap();
+        assert_eq!(inner.borrow().get_global(index), Ok(Object::Integer(2)));
+        assert_eq!(
+            global.borrow().variables.get("g"),
+            Some(&Object::Integer(2))
+        );
     }
 }
@@ -1,48 +1,121 @@
-use crate::{lexer::Lexer, statement::*, token::Token};
-
-type Precedence = i8;
-const PRECEDENCE_LOWEST: Precedence = 0;
-const PRECEDENCE_EQUAL: Precedence = 1; // ==
-const PRECEDENCE_GREATER_LESS: Precedence = 2; // >, >=, <, <=
-const PRECEDENCE_SUM: Precedence = 3; // + -
-const PRECEDENCE_PRODUCT: Precedence = 4; // * /
-const PRECEDENCE_PREFIX: Precedence = 5; // !X, -X
-const PRECEDENCE_PARENTHESE: Precedence = 6; // ()
-const PRECEDENCE_INDEX: Precedence = 7; // A[i]
+use crate::{
+    error::{InterpreterError, ParseError},
+    lexer::Lexer,
+    span::{Span, Spanned},
+    statement::{self, *},
+    token::Token,
+};
+
+use std::fmt;
+
+pub(crate) type Precedence = i8;
+pub(crate) const PRECEDENCE_LOWEST: Precedence = 0;
+pub(crate) const PRECEDENCE_ASSIGN: Precedence = 1; // A[i] = X
+const PRECEDENCE_EQUAL: Precedence = 2; // ==
+const PRECEDENCE_GREATER_LESS: Precedence = 3; // >, >=, <, <=
+pub(crate) const PRECEDENCE_RANGE: Precedence = 4; // ..
+const PRECEDENCE_BIT_OR: Precedence = 5; // |
+const PRECEDENCE_BIT_XOR: Precedence = 6; // ^
+const PRECEDENCE_BIT_AND: Precedence = 7; // &
+const PRECEDENCE_SHIFT: Precedence = 8; // << >>
+const PRECEDENCE_SUM: Precedence = 9; // + -
+const PRECEDENCE_PRODUCT: Precedence = 10; // * / // %
+pub(crate) const PRECEDENCE_PREFIX: Precedence = 11; // !X, -X, ~X
+pub(crate) const PRECEDENCE_POWER: Precedence = 12; // **
+pub(crate) const PRECEDENCE_PARENTHESE: Precedence = 13; // ()
+const PRECEDENCE_INDEX: Precedence = 14; // A[i]
 
 pub struct Parser {
     lexer: Lexer,
     cur_token: Token,
+    cur_span: Span,
     peek_token: Token,
+    peek_span: Span,
+    errors: Vec<InterpreterError>,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Program {
-    pub statements: Vec<Statement>,
+    pub statements: Vec<Spanned<Statement>>,
+}
+
+impl Program {
+    // The program with every Group replaced by the expression in it
+    pub fn ungrouped(mut self) -> Self {
+        statement::ungroup_statements(&mut self.statements);
+        self
+    }
+}
+
+// Canonical source of the program, parsing it again gives the same AST up to
+// the groups, see Program::ungrouped
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        statement::write_statements(f, &self.statements, 0, true)
+    }
 }
 
 impl Parser {
     pub fn new(mut lexer: Lexer) -> Self {
-        let cur_token = lexer.next_token();
-        let peek_token = lexer.next_token();
+        let (cur_token, cur_span) = lexer.next_token();
+        let (peek_token, peek_span) = lexer.next_token();
         Parser {
             lexer,
             cur_token,
+            cur_span,
             peek_token,
+            peek_span,
+            errors: vec![],
         }
     }
 
     pub fn next_token(&mut self) {
         std::mem::swap(&mut self.cur_token, &mut self.peek_token);
-        self.peek_token = self.lexer.next_token();
+        self.cur_span = self.peek_span;
+        (self.peek_token, self.peek_span) = self.lexer.next_token();
+    }
+
+    // Error for an unexpected cur_token, illegal tokens report why they could
+    // not be lexed instead
+    fn error(&self, expected: &str) -> InterpreterError {
+        Self::unexpected_token(expected, &self.cur_token, self.cur_span)
+    }
+
+    // Error for an unexpected peek_token
+    fn peek_error(&self, expected: &str) -> InterpreterError {
+        Self::unexpected_token(expected, &self.peek_token, self.peek_span)
     }
 
-    pub fn parse_program(&mut self) -> Result<Program, String> {
+    fn unexpected_token(expected: &str, found: &Token, span: Span) -> InterpreterError {
+        match found {
+            Token::Illegal(e) => InterpreterError::Lex(e.clone(), span),
+            found => InterpreterError::Parse(
+                ParseError::UnexpectedToken {
+                    expected: expected.to_string(),
+                    found: found.clone(),
+                },
+                span,
+            ),
+        }
+    }
+
+    // Statements that failed to parse are left out of the program, parsing
+    // carries on after them so every syntax error is reported in one run
+    pub fn parse_program(&mut self) -> (Program, Vec<InterpreterError>) {
         let mut program = Program { statements: vec![] };
 
         while self.cur_token != Token::Eof {
             match self.parse_statement() {
                 Ok(statement) => program.statements.push(statement),
-                Err(e) => return Err(e),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize();
+                    // A stray RCurlyBracket can not start a statement either
+                    if self.cur_token == Token::RCurlyBracket {
+                        self.next_token();
+                    }
+                    continue;
+                }
             }
 
             // Skip through last token from parsed statement
@@ -53,49 +126,77 @@ impl Parser {
             }
         }
 
-        Ok(program)
+        (program, std::mem::take(&mut self.errors))
     }
 
-    fn parse_statement(&mut self) -> Result<Statement, String> {
-        match self.cur_token {
-            Token::Let => match self.parse_let_statement() {
-                Ok(statement) => Ok(statement),
-                Err(e) => Err(e),
-            },
-            Token::Return => match self.parse_return_statement() {
-                Ok(statement) => Ok(statement),
-                Err(e) => Err(e),
-            },
-            _ => match self.parse_expression_statement(PRECEDENCE_LOWEST) {
-                Ok(statement) => Ok(Statement::Expression(statement)),
-                Err(e) => Err(e),
-            },
+    // Skip tokens after a syntax error until a new statement can start: past
+    // a semicolon, or at a RCurlyBracket, let, return, a loop or the end of input
+    fn synchronize(&mut self) {
+        loop {
+            match self.cur_token {
+                Token::Semicolon => {
+                    self.next_token();
+                    return;
+                }
+                Token::RCurlyBracket
+                | Token::Let
+                | Token::Return
+                | Token::While
+                | Token::For
+                | Token::Eof => return,
+                _ => self.next_token(),
+            }
         }
     }
 
-    fn get_precedence(token: &Token) -> Precedence {
+    fn parse_statement(&mut self) -> Result<Spanned<Statement>, InterpreterError> {
+        let start = self.cur_span;
+        let statement = match self.cur_token {
+            Token::Let => self.parse_let_statement()?,
+            Token::Return => self.parse_return_statement()?,
+            Token::While => self.parse_while_statement(None)?,
+            Token::For => self.parse_for_statement(None)?,
+            Token::Ident(_) if self.peek_token == Token::Colon => self.parse_labeled_statement()?,
+            Token::Break => Statement::Break(self.parse_label()),
+            Token::Continue => Statement::Continue(self.parse_label()),
+            _ => Statement::Expression(self.parse_expression_statement(PRECEDENCE_LOWEST)?),
+        };
+        Ok(Spanned::new(statement, start.to(self.cur_span)))
+    }
+
+    pub(crate) fn get_precedence(token: &Token) -> Precedence {
         match token {
             Token::LSquareBracket => PRECEDENCE_INDEX,
             Token::LParen => PRECEDENCE_PARENTHESE,
+            Token::Dot => PRECEDENCE_INDEX,
+            Token::Assign
+            | Token::PlusAssign
+            | Token::MinusAssign
+            | Token::AsteriskAssign
+            | Token::SlashAssign
+            | Token::PercentAssign => PRECEDENCE_ASSIGN,
             Token::Equal | Token::NotEqual => PRECEDENCE_EQUAL,
             Token::Plus | Token::Minus => PRECEDENCE_SUM,
-            Token::Asterisk | Token::Slash => PRECEDENCE_PRODUCT,
+            Token::Asterisk | Token::Slash | Token::DoubleSlash | Token::Percent => {
+                PRECEDENCE_PRODUCT
+            }
+            Token::DoubleAsterisk => PRECEDENCE_POWER,
             Token::Gt | Token::Gte | Token::Lt | Token::Lte => PRECEDENCE_GREATER_LESS,
+            Token::DotDot => PRECEDENCE_RANGE,
+            Token::Pipe => PRECEDENCE_BIT_OR,
+            Token::Caret => PRECEDENCE_BIT_XOR,
+            Token::Ampersand => PRECEDENCE_BIT_AND,
+            Token::ShiftLeft | Token::ShiftRight => PRECEDENCE_SHIFT,
             _ => PRECEDENCE_LOWEST,
         }
     }
 
-    fn parse_let_statement(&mut self) -> Result<Statement, String> {
+    fn parse_let_statement(&mut self) -> Result<Statement, InterpreterError> {
         // Skip through let token
         self.next_token();
         let literal = match &self.cur_token {
             Token::Ident(literal) => literal.clone(),
-            _ => {
-                return Err(format!(
-                    "Expected Token::Ident got {:?}",
-                    self.cur_token.clone()
-                ))
-            }
+            _ => return Err(self.error("Token::Ident")),
         };
         if self.peek_token != Token::Assign {
             return Ok(Statement::Let(literal, None));
@@ -120,7 +221,15 @@ impl Parser {
         Ok(let_statement)
     }
 
-    fn parse_return_statement(&mut self) -> Result<Statement, String> {
+    fn parse_return_statement(&mut self) -> Result<Statement, InterpreterError> {
+        // Nothing follows return at the end of its statement or block, the
+        // function returns null
+        if matches!(
+            self.peek_token,
+            Token::Semicolon | Token::RCurlyBracket | Token::Eof
+        ) {
+            return Ok(Statement::Return(None));
+        }
         // Skip through return token
         self.next_token();
         match self.parse_expression_statement(PRECEDENCE_LOWEST) {
@@ -129,59 +238,161 @@ impl Parser {
         }
     }
 
+    // Parse label: while ... or label: for ...
+    fn parse_labeled_statement(&mut self) -> Result<Statement, InterpreterError> {
+        let label = match &self.cur_token {
+            Token::Ident(label) => label.clone(),
+            _ => return Err(self.error("Token::Ident")),
+        };
+        // Skip through label
+        self.next_token();
+        // Skip through colon token
+        self.next_token();
+        match self.cur_token {
+            Token::While => self.parse_while_statement(Some(label)),
+            Token::For => self.parse_for_statement(Some(label)),
+            _ => Err(self.error("Token::While or Token::For")),
+        }
+    }
+
+    // Label after break or continue, it has to be on the same line so that a
+    // statement following a bare break on the next line is not taken for one
+    fn parse_label(&mut self) -> Option<String> {
+        match &self.peek_token {
+            Token::Ident(label) if self.peek_span.line == self.cur_span.line => {
+                let label = label.clone();
+                self.next_token();
+                Some(label)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_while_statement(
+        &mut self,
+        label: Option<String>,
+    ) -> Result<Statement, InterpreterError> {
+        // Skip through while token
+        self.next_token();
+        let condition = self.parse_expression_statement(PRECEDENCE_LOWEST)?;
+        // Skip through expression
+        self.next_token();
+        if self.cur_token != Token::LCurlyBracket {
+            return Err(self.error("Token::LCurlyBracket"));
+        }
+        let body = self.parse_block()?;
+
+        Ok(Statement::While {
+            label,
+            condition,
+            body,
+        })
+    }
+
+    fn parse_for_statement(
+        &mut self,
+        label: Option<String>,
+    ) -> Result<Statement, InterpreterError> {
+        // Skip through for token
+        self.next_token();
+        let variable = match &self.cur_token {
+            Token::Ident(variable) => variable.clone(),
+            _ => return Err(self.error("Token::Ident")),
+        };
+        if self.peek_token != Token::In {
+            return Err(self.peek_error("Token::In"));
+        }
+        // Skip through identifier token
+        self.next_token();
+        // Skip through in token
+        self.next_token();
+        let iterable = self.parse_expression_statement(PRECEDENCE_LOWEST)?;
+        // Skip through expression
+        self.next_token();
+        if self.cur_token != Token::LCurlyBracket {
+            return Err(self.error("Token::LCurlyBracket"));
+        }
+        let body = self.parse_block()?;
+
+        Ok(Statement::For {
+            label,
+            variable,
+            iterable,
+            body,
+        })
+    }
+
     fn parse_expression_statement(
         &mut self,
         precedence: Precedence,
-    ) -> Result<ExpressionStatement, String> {
+    ) -> Result<Spanned<ExpressionStatement>, InterpreterError> {
+        let start = self.cur_span;
+
         // Match Prefix Parse
-        let mut left = match &self.cur_token {
-            Token::Bang | Token::Minus => {
+        let prefix = match &self.cur_token {
+            Token::Bang | Token::Minus | Token::Tilde => {
                 let operator = self.cur_token.clone();
                 // Skip through operator token
                 self.next_token();
 
-                let right = match self.parse_expression_statement(PRECEDENCE_PREFIX) {
-                    Ok(statement) => statement,
-                    Err(e) => return Err(e),
-                };
+                let right = self.parse_expression_statement(PRECEDENCE_PREFIX)?;
                 ExpressionStatement::Prefix {
                     operator,
                     right: Box::new(right),
                 }
             }
+            Token::Int(num) => ExpressionStatement::Int(*num),
             Token::Num(num) => ExpressionStatement::Num(*num),
-            Token::Ident(literal) => ExpressionStatement::Identifier(literal.clone()),
+            Token::Str(s) => ExpressionStatement::Str(s.clone()),
+            Token::Ident(literal) => {
+                ExpressionStatement::Identifier(literal.clone(), Binding::Unresolved)
+            }
             Token::True => ExpressionStatement::Bool(true),
             Token::False => ExpressionStatement::Bool(false),
             Token::LParen => self.parse_group_expression()?,
+            // Blocks are only parsed after if, else and fn, so a curly bracket
+            // starting an expression is always a hash literal
+            Token::LCurlyBracket => self.parse_hash_expression()?,
+            Token::LSquareBracket => {
+                ExpressionStatement::Array(self.parse_expression_list(Token::RSquareBracket)?)
+            }
             Token::If => self.parse_if_expression()?,
             Token::Fn => self.parse_fn_expression()?,
-            _ => {
-                return Err(format!(
-                    "No Prefix Parse arm for token = {:?}",
-                    self.cur_token
-                ))
-            }
+            _ => return Err(self.error("an expression")),
         };
+        let mut left = Spanned::new(prefix, start.to(self.cur_span));
 
         // Match Infix Parse
         while self.peek_token != Token::Semicolon
             && precedence < Self::get_precedence(&self.peek_token)
         {
-            left = match &self.peek_token {
+            let infix = match &self.peek_token {
                 Token::Plus
                 | Token::Minus
                 | Token::Slash
                 | Token::Asterisk
+                | Token::Percent
+                | Token::DoubleAsterisk
+                | Token::DoubleSlash
                 | Token::Equal
                 | Token::NotEqual
                 | Token::Gt
                 | Token::Gte
                 | Token::Lt
-                | Token::Lte => {
+                | Token::Lte
+                | Token::Ampersand
+                | Token::Pipe
+                | Token::Caret
+                | Token::ShiftLeft
+                | Token::ShiftRight => {
                     // Skip through prefix expression
                     self.next_token();
-                    let precedence = Self::get_precedence(&self.cur_token);
+                    let mut precedence = Self::get_precedence(&self.cur_token);
+                    // ** is right associative, so its right side may contain
+                    // another ** of the same precedence
+                    if self.cur_token == Token::DoubleAsterisk {
+                        precedence -= 1;
+                    }
                     let operator = self.cur_token.clone();
                     // Skip through operator token
                     self.next_token();
@@ -194,65 +405,209 @@ impl Parser {
                         Err(e) => return Err(e),
                     }
                 }
-                Token::LParen => {
+                Token::DotDot => {
                     // Skip through prefix expression
                     self.next_token();
-                    // Skip through Token::LParen
+                    // Skip through dot dot token
                     self.next_token();
-
-                    let mut args = vec![];
-                    while self.cur_token != Token::RParen {
-                        match self.parse_expression_statement(PRECEDENCE_LOWEST) {
-                            Ok(e) => args.push(e),
-                            Err(e) => return Err(e),
-                        };
-                        // Skip through expression
-                        self.next_token();
-                        match self.cur_token {
-                            Token::RParen => break,
-                            Token::Comma => {
-                                self.next_token();
-                            }
-                            _ => {
-                                return Err(format!(
-                                    "Expected Token::RParen or Token::Comma, got={:?}",
-                                    self.cur_token
-                                ))
-                            }
-                        }
+                    let end = self.parse_expression_statement(PRECEDENCE_RANGE)?;
+                    ExpressionStatement::Range {
+                        start: Box::new(left),
+                        end: Box::new(end),
                     }
-
-                    // Skip through Token::RParen
+                }
+                Token::LParen => {
+                    // Skip through prefix expression
                     self.next_token();
 
+                    let args = self.parse_expression_list(Token::RParen)?;
                     ExpressionStatement::Call {
                         caller: Box::new(left),
-                        args: args,
+                        args,
+                    }
+                }
+                Token::LSquareBracket => {
+                    // Skip through prefix expression
+                    self.next_token();
+                    self.parse_index_expression(left)?
+                }
+                Token::Dot => {
+                    // Skip through prefix expression
+                    self.next_token();
+                    // Skip through dot token
+                    self.next_token();
+                    match &self.cur_token {
+                        Token::Ident(name) => ExpressionStatement::Field {
+                            left: Box::new(left),
+                            name: name.clone(),
+                        },
+                        _ => return Err(self.error("Token::Ident")),
+                    }
+                }
+                Token::Assign
+                | Token::PlusAssign
+                | Token::MinusAssign
+                | Token::AsteriskAssign
+                | Token::SlashAssign
+                | Token::PercentAssign => {
+                    match left.node {
+                        ExpressionStatement::Identifier(..)
+                        | ExpressionStatement::Index { .. }
+                        | ExpressionStatement::Field { .. } => {}
+                        _ => {
+                            return Err(InterpreterError::Parse(
+                                ParseError::InvalidAssignmentTarget(left.node),
+                                left.span,
+                            ))
+                        }
+                    }
+                    // Skip through prefix expression
+                    self.next_token();
+                    let operator = match self.cur_token {
+                        Token::PlusAssign => Some(Token::Plus),
+                        Token::MinusAssign => Some(Token::Minus),
+                        Token::AsteriskAssign => Some(Token::Asterisk),
+                        Token::SlashAssign => Some(Token::Slash),
+                        Token::PercentAssign => Some(Token::Percent),
+                        _ => None,
+                    };
+                    // Skip through assign token
+                    self.next_token();
+
+                    // Assignment is right associative: a[0] = b[0] = 1
+                    let value = self.parse_expression_statement(PRECEDENCE_ASSIGN - 1)?;
+                    ExpressionStatement::Assign {
+                        target: Box::new(left),
+                        operator,
+                        value: Box::new(value),
                     }
                 }
                 _ => return Ok(left),
             };
+            left = Spanned::new(infix, start.to(self.cur_span));
         }
 
         Ok(left)
     }
 
-    fn parse_group_expression(&mut self) -> Result<ExpressionStatement, String> {
+    // Parse comma separated expressions following cur_token up to the end token,
+    // cur_token is left at the end token. A trailing comma is allowed
+    fn parse_expression_list(
+        &mut self,
+        end: Token,
+    ) -> Result<Vec<Spanned<ExpressionStatement>>, InterpreterError> {
+        // Skip through opening token
+        self.next_token();
+
+        let mut list = vec![];
+        while self.cur_token != end {
+            list.push(self.parse_expression_statement(PRECEDENCE_LOWEST)?);
+            // Skip through expression
+            self.next_token();
+            match &self.cur_token {
+                token if *token == end => break,
+                Token::Comma => self.next_token(),
+                _ => return Err(self.error(&format!("Token::{:?} or Token::Comma", end))),
+            }
+        }
+
+        Ok(list)
+    }
+
+    // Parse {key: value, ...}, cur_token is left at the RCurlyBracket
+    fn parse_hash_expression(&mut self) -> Result<ExpressionStatement, InterpreterError> {
+        // Skip through LCurlyBracket token
+        self.next_token();
+
+        let mut pairs = vec![];
+        while self.cur_token != Token::RCurlyBracket {
+            let key = self.parse_expression_statement(PRECEDENCE_LOWEST)?;
+            if self.peek_token != Token::Colon {
+                return Err(self.peek_error("Token::Colon"));
+            }
+            // Skip through key expression
+            self.next_token();
+            // Skip through colon token
+            self.next_token();
+            let value = self.parse_expression_statement(PRECEDENCE_LOWEST)?;
+            pairs.push((key, value));
+            // Skip through value expression
+            self.next_token();
+            match self.cur_token {
+                Token::RCurlyBracket => break,
+                Token::Comma => self.next_token(),
+                _ => return Err(self.error("Token::RCurlyBracket or Token::Comma")),
+            }
+        }
+
+        Ok(ExpressionStatement::Hash(pairs))
+    }
+
+    // Parse a[i] or a[start:end] where either bound of the slice may be left out,
+    // cur_token starts at the LSquareBracket and is left at the RSquareBracket
+    fn parse_index_expression(
+        &mut self,
+        left: Spanned<ExpressionStatement>,
+    ) -> Result<ExpressionStatement, InterpreterError> {
+        // Skip through LSquareBracket token
+        self.next_token();
+
+        let mut start = None;
+        if self.cur_token != Token::Colon {
+            start = Some(Box::new(
+                self.parse_expression_statement(PRECEDENCE_LOWEST)?,
+            ));
+            // Skip through expression
+            self.next_token();
+        }
+
+        match (&self.cur_token, start) {
+            (Token::RSquareBracket, Some(index)) => Ok(ExpressionStatement::Index {
+                left: Box::new(left),
+                index,
+            }),
+            (Token::Colon, start) => {
+                // Skip through colon token
+                self.next_token();
+
+                let mut end = None;
+                if self.cur_token != Token::RSquareBracket {
+                    end = Some(Box::new(
+                        self.parse_expression_statement(PRECEDENCE_LOWEST)?,
+                    ));
+                    // Skip through expression
+                    self.next_token();
+                }
+                if self.cur_token != Token::RSquareBracket {
+                    return Err(self.error("Token::RSquareBracket"));
+                }
+
+                Ok(ExpressionStatement::Slice {
+                    left: Box::new(left),
+                    start,
+                    end,
+                })
+            }
+            _ => Err(self.error("Token::RSquareBracket or Token::Colon")),
+        }
+    }
+
+    fn parse_group_expression(&mut self) -> Result<ExpressionStatement, InterpreterError> {
         // Skip through Token::LParen
         self.next_token();
 
         let expression = self.parse_expression_statement(PRECEDENCE_LOWEST)?;
 
         if self.peek_token != Token::RParen {
-            return Err(format!("Expected RParen, got={:?}", self.peek_token));
+            return Err(self.peek_error("Token::RParen"));
         }
         // Skip through expression
         self.next_token();
 
-        return Ok(ExpressionStatement::Group(Box::new(expression)));
+        Ok(ExpressionStatement::Group(Box::new(expression)))
     }
 
-    fn parse_if_expression(&mut self) -> Result<ExpressionStatement, String> {
+    fn parse_if_expression(&mut self) -> Result<ExpressionStatement, InterpreterError> {
         // Skip through Token::If
         self.next_token();
 
@@ -261,39 +616,23 @@ impl Parser {
             has_lparen = true;
             self.next_token();
         }
-        let condition = match self.parse_expression_statement(PRECEDENCE_LOWEST) {
-            Ok(statement) => statement,
-            Err(e) => return Err(e),
-        };
+        let condition = self.parse_expression_statement(PRECEDENCE_LOWEST)?;
         // Skip through expression
         self.next_token();
 
         if has_lparen {
             if self.cur_token != Token::RParen {
-                return Err(format!("Expected RParen, got = {:?}", self.peek_token));
+                return Err(self.error("Token::RParen"));
             }
             // Skip through RParen
             self.next_token()
         }
 
         if self.cur_token != Token::LCurlyBracket {
-            return Err(format!("Expected LBracket, got = {:?}", self.cur_token));
+            return Err(self.error("Token::LCurlyBracket"));
         }
 
-        // Skip through LBracket Token
-        self.next_token();
-
-        // Start parsing outcome until facing RBracket
-        let mut outcome = vec![];
-        while self.cur_token != Token::RCurlyBracket {
-            let statement = match self.parse_statement() {
-                Ok(statement) => statement,
-                Err(e) => return Err(e),
-            };
-            outcome.push(statement);
-            // Skip through expression
-            self.next_token();
-        }
+        let outcome = self.parse_block()?;
 
         if self.peek_token != Token::Else {
             return Ok(ExpressionStatement::If {
@@ -310,29 +649,21 @@ impl Parser {
         self.next_token();
 
         match self.cur_token {
-            Token::If => match self.parse_if_expression() {
-                Ok(statement) => Ok(ExpressionStatement::If {
+            Token::If => {
+                let start = self.cur_span;
+                let statement = self.parse_if_expression()?;
+                let span = start.to(self.cur_span);
+                Ok(ExpressionStatement::If {
                     condition: Box::new(condition),
                     outcome,
-                    alternate: vec![Statement::Expression(statement)],
-                }),
-                Err(e) => return Err(e),
-            },
+                    alternate: vec![Spanned::new(
+                        Statement::Expression(Spanned::new(statement, span)),
+                        span,
+                    )],
+                })
+            }
             Token::LCurlyBracket => {
-                // Skip through LBracket Token
-                self.next_token();
-
-                // Start parsing outcome until facing RBracket
-                let mut alternate = vec![];
-                while self.cur_token != Token::RCurlyBracket {
-                    let statement = match self.parse_statement() {
-                        Ok(statement) => statement,
-                        Err(e) => return Err(e),
-                    };
-                    alternate.push(statement);
-                    // Skip through expression
-                    self.next_token();
-                }
+                let alternate = self.parse_block()?;
 
                 Ok(ExpressionStatement::If {
                     condition: Box::new(condition),
@@ -340,67 +671,77 @@ impl Parser {
                     alternate,
                 })
             }
-            _ => Err(format!(
-                "Expected Token::LBracket or Token::If, got = {:?}",
-                self.cur_token
-            )),
+            _ => Err(self.error("Token::LBracket or Token::If")),
         }
     }
 
-    fn parse_fn_expression(&mut self) -> Result<ExpressionStatement, String> {
+    fn parse_fn_expression(&mut self) -> Result<ExpressionStatement, InterpreterError> {
         // Skip through fn token
         self.next_token();
         if self.cur_token != Token::LParen {
-            return Err(format!("Expected Token::LParen, got={:?}", self.cur_token));
+            return Err(self.error("Token::LParen"));
         }
         // Skip through LParen token
         self.next_token();
-        let args = match self.parse_fn_args() {
-            Ok(args) => args,
-            Err(e) => return Err(e),
-        };
+        let args = self.parse_fn_args()?;
         if self.cur_token != Token::LCurlyBracket {
-            return Err(format!(
-                "Expected Token::LCurlyBracket, got={:?}",
-                self.cur_token
-            ));
+            return Err(self.error("Token::LCurlyBracket"));
         }
+        let body = self.parse_block()?;
+
+        Ok(ExpressionStatement::Fn { args, body })
+    }
+
+    // Parse statements between curly brackets, cur_token is left at the RCurlyBracket
+    fn parse_block(&mut self) -> Result<Vec<Spanned<Statement>>, InterpreterError> {
         // Skip through LCurlyBracket token
         self.next_token();
-        let mut body = vec![];
+
+        let mut block = vec![];
         while self.cur_token != Token::RCurlyBracket {
+            if self.cur_token == Token::Eof {
+                return Err(self.error("Token::RCurlyBracket"));
+            }
             match self.parse_statement() {
-                Ok(stmt) => body.push(stmt),
-                Err(e) => return Err(e),
+                Ok(statement) => block.push(statement),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize();
+                    continue;
+                }
             }
             // Skip through statement
-            self.next_token()
+            self.next_token();
+            // Skip through semi colon (optional)
+            if self.cur_token == Token::Semicolon {
+                self.next_token();
+            }
         }
 
-        return Ok(ExpressionStatement::Fn { args, body });
+        Ok(block)
     }
 
-    fn parse_fn_args(&mut self) -> Result<Vec<String>, String> {
+    fn parse_fn_args(&mut self) -> Result<Vec<String>, InterpreterError> {
         let mut args = vec![];
 
         if self.cur_token != Token::RParen {
             match &self.cur_token {
                 Token::Ident(arg) => args.push(arg.clone()),
-                _ => return Err(format!("Expected Token::Ident, got={:?}", self.cur_token)),
+                _ => return Err(self.error("Token::Ident")),
             }
             // Skip through Ident token
             self.next_token();
 
             while self.cur_token != Token::RParen {
                 if self.cur_token != Token::Comma {
-                    return Err(format!("Expected Token::Comma, got={:?}", self.cur_token));
+                    return Err(self.error("Token::Comma"));
                 }
                 // Skip through Comma token
                 self.next_token();
 
                 match &self.cur_token {
                     Token::Ident(arg) => args.push(arg.clone()),
-                    _ => return Err(format!("Expected Token::Ident, got={:?}", self.cur_token)),
+                    _ => return Err(self.error("Token::Ident")),
                 }
                 // Skip through Ident token
                 self.next_token();
@@ -417,18 +758,30 @@ impl Parser {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::error::LexError;
+
+    // Node with a placeholder span, spans are ignored when comparing nodes
+    fn node<T>(node: T) -> Spanned<T> {
+        Spanned::new(node, Span::default())
+    }
+
     #[test]
     fn test_parser() {
         struct Testcase<'a> {
             name: &'a str,
             input: String,
-            expected: Vec<Statement>,
+            expected: Vec<Spanned<Statement>>,
         }
         let testcases: Vec<Testcase> = vec![
             Testcase {
                 name: "simple let",
                 input: String::from("let a"),
-                expected: vec![Statement::Let("a".to_string(), None)],
+                expected: vec![node(Statement::Let("a".to_string(), None))],
+            },
+            Testcase {
+                name: "bare return",
+                input: String::from("return;\nreturn"),
+                expected: vec![node(Statement::Return(None)), node(Statement::Return(None))],
             },
             Testcase {
                 name: "let and return",
@@ -437,41 +790,123 @@ mod test {
                     return 5",
                 ),
                 expected: vec![
-                    Statement::Let("a".to_string(), Some(ExpressionStatement::Num(6.5 as f64))),
-                    Statement::Return(Some(ExpressionStatement::Num(5 as f64))),
+                    node(Statement::Let(
+                        "a".to_string(),
+                        Some(node(ExpressionStatement::Num(6.5))),
+                    )),
+                    node(Statement::Return(Some(node(ExpressionStatement::Int(5))))),
                 ],
             },
             Testcase {
                 name: "let and expression parsing",
                 input: String::from("let a = 5+6+7"),
-                expected: vec![Statement::Let(
+                expected: vec![node(Statement::Let(
                     "a".to_string(),
-                    Some(ExpressionStatement::Infix {
-                        left: Box::new(ExpressionStatement::Infix {
-                            left: Box::new(ExpressionStatement::Num(5 as f64)),
+                    Some(node(ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Int(5))),
                             operator: Token::Plus,
-                            right: Box::new(ExpressionStatement::Num(6 as f64)),
-                        }),
+                            right: Box::new(node(ExpressionStatement::Int(6))),
+                        })),
                         operator: Token::Plus,
-                        right: Box::new(ExpressionStatement::Num(7 as f64)),
-                    }),
-                )],
+                        right: Box::new(node(ExpressionStatement::Int(7))),
+                    })),
+                ))],
             },
             Testcase {
                 name: "let and expression with different precedence check",
                 input: String::from("let a = 5 + 6 / 7"),
-                expected: vec![Statement::Let(
+                expected: vec![node(Statement::Let(
                     "a".to_string(),
-                    Some(ExpressionStatement::Infix {
-                        left: Box::new(ExpressionStatement::Num(5 as f64)),
+                    Some(node(ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Int(5))),
                         operator: Token::Plus,
-                        right: Box::new(ExpressionStatement::Infix {
-                            left: Box::new(ExpressionStatement::Num(6 as f64)),
+                        right: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Int(6))),
                             operator: Token::Slash,
-                            right: Box::new(ExpressionStatement::Num(7 as f64)),
-                        }),
-                    }),
-                )],
+                            right: Box::new(node(ExpressionStatement::Int(7))),
+                        })),
+                    })),
+                ))],
+            },
+            Testcase {
+                name: "power is right associative and binds tighter than product",
+                input: String::from("2 * 3 ** 2 ** 2"),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Int(2))),
+                        operator: Token::Asterisk,
+                        right: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Int(3))),
+                            operator: Token::DoubleAsterisk,
+                            right: Box::new(node(ExpressionStatement::Infix {
+                                left: Box::new(node(ExpressionStatement::Int(2))),
+                                operator: Token::DoubleAsterisk,
+                                right: Box::new(node(ExpressionStatement::Int(2))),
+                            })),
+                        })),
+                    },
+                )))],
+            },
+            Testcase {
+                name: "power binds tighter than prefix minus",
+                input: String::from("-2 ** 2"),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Prefix {
+                        operator: Token::Minus,
+                        right: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Int(2))),
+                            operator: Token::DoubleAsterisk,
+                            right: Box::new(node(ExpressionStatement::Int(2))),
+                        })),
+                    },
+                )))],
+            },
+            Testcase {
+                name: "product operators are left associative",
+                input: String::from("7 // 2 % 3 * 4"),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Infix {
+                                left: Box::new(node(ExpressionStatement::Int(7))),
+                                operator: Token::DoubleSlash,
+                                right: Box::new(node(ExpressionStatement::Int(2))),
+                            })),
+                            operator: Token::Percent,
+                            right: Box::new(node(ExpressionStatement::Int(3))),
+                        })),
+                        operator: Token::Asterisk,
+                        right: Box::new(node(ExpressionStatement::Int(4))),
+                    },
+                )))],
+            },
+            Testcase {
+                name: "bitwise operator precedence",
+                input: String::from("1 | 2 ^ 3 & 4 << 5 + 6"),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Int(1))),
+                        operator: Token::Pipe,
+                        right: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Int(2))),
+                            operator: Token::Caret,
+                            right: Box::new(node(ExpressionStatement::Infix {
+                                left: Box::new(node(ExpressionStatement::Int(3))),
+                                operator: Token::Ampersand,
+                                right: Box::new(node(ExpressionStatement::Infix {
+                                    left: Box::new(node(ExpressionStatement::Int(4))),
+                                    operator: Token::ShiftLeft,
+                                    right: Box::new(node(ExpressionStatement::Infix {
+                                        left: Box::new(node(ExpressionStatement::Int(5))),
+                                        operator: Token::Plus,
+                                        right: Box::new(node(ExpressionStatement::Int(6))),
+                                    })),
+                                })),
+                            })),
+                        })),
+                    },
+                )))],
             },
             Testcase {
                 name: "if expression",
@@ -485,31 +920,39 @@ mod test {
                     }
                     ",
                 ),
-                expected: vec![Statement::Expression(ExpressionStatement::If {
-                    condition: Box::new(ExpressionStatement::Infix {
-                        left: Box::new(ExpressionStatement::Identifier("a".to_string())),
+                expected: vec![node(Statement::Expression(node(ExpressionStatement::If {
+                    condition: Box::new(node(ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Identifier(
+                            "a".to_string(),
+                            Binding::Unresolved,
+                        ))),
                         operator: Token::Equal,
-                        right: Box::new(ExpressionStatement::Num(5 as f64)),
-                    }),
-                    outcome: vec![Statement::Let(
+                        right: Box::new(node(ExpressionStatement::Int(5))),
+                    })),
+                    outcome: vec![node(Statement::Let(
                         "b".to_string(),
-                        Some(ExpressionStatement::Num(10 as f64)),
-                    )],
-                    alternate: vec![Statement::Expression(ExpressionStatement::If {
-                        condition: Box::new(ExpressionStatement::Infix {
-                            left: Box::new(ExpressionStatement::Identifier("c".to_string())),
+                        Some(node(ExpressionStatement::Int(10))),
+                    ))],
+                    alternate: vec![node(Statement::Expression(node(ExpressionStatement::If {
+                        condition: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Identifier(
+                                "c".to_string(),
+                                Binding::Unresolved,
+                            ))),
                             operator: Token::Gte,
-                            right: Box::new(ExpressionStatement::Num(2 as f64)),
-                        }),
-                        outcome: vec![Statement::Expression(ExpressionStatement::Prefix {
-                            operator: Token::Bang,
-                            right: Box::new(ExpressionStatement::Num(3 as f64)),
-                        })],
-                        alternate: vec![Statement::Expression(ExpressionStatement::Identifier(
-                            "gg".to_string(),
-                        ))],
-                    })],
-                })],
+                            right: Box::new(node(ExpressionStatement::Int(2))),
+                        })),
+                        outcome: vec![node(Statement::Expression(node(
+                            ExpressionStatement::Prefix {
+                                operator: Token::Bang,
+                                right: Box::new(node(ExpressionStatement::Int(3))),
+                            },
+                        )))],
+                        alternate: vec![node(Statement::Expression(node(
+                            ExpressionStatement::Identifier("gg".to_string(), Binding::Unresolved),
+                        )))],
+                    })))],
+                })))],
             },
             Testcase {
                 name: "fn expression",
@@ -519,56 +962,453 @@ mod test {
                     return d
                 }",
                 ),
-                expected: vec![Statement::Let(
+                expected: vec![node(Statement::Let(
                     "a".to_string(),
-                    Some(ExpressionStatement::Fn {
+                    Some(node(ExpressionStatement::Fn {
                         args: vec!["b".to_string(), "c".to_string()],
                         body: vec![
-                            Statement::Let(
+                            node(Statement::Let(
                                 "d".to_string(),
-                                Some(ExpressionStatement::Infix {
-                                    left: Box::new(ExpressionStatement::Identifier(
+                                Some(node(ExpressionStatement::Infix {
+                                    left: Box::new(node(ExpressionStatement::Identifier(
                                         "b".to_string(),
-                                    )),
+                                        Binding::Unresolved,
+                                    ))),
                                     operator: Token::Plus,
-                                    right: Box::new(ExpressionStatement::Identifier(
+                                    right: Box::new(node(ExpressionStatement::Identifier(
                                         "c".to_string(),
-                                    )),
-                                }),
-                            ),
-                            Statement::Return(Some(ExpressionStatement::Identifier(
-                                "d".to_string(),
-                            ))),
+                                        Binding::Unresolved,
+                                    ))),
+                                })),
+                            )),
+                            node(Statement::Return(Some(node(
+                                ExpressionStatement::Identifier(
+                                    "d".to_string(),
+                                    Binding::Unresolved,
+                                ),
+                            )))),
                         ],
-                    }),
-                )],
+                    })),
+                ))],
             },
             Testcase {
                 name: "call expression",
                 input: String::from("abc(def)"),
-                expected: vec![Statement::Expression(ExpressionStatement::Call {
-                    caller: Box::new(ExpressionStatement::Identifier("abc".to_string())),
-                    args: vec![ExpressionStatement::Identifier("def".to_string())],
-                })],
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Call {
+                        caller: Box::new(node(ExpressionStatement::Identifier(
+                            "abc".to_string(),
+                            Binding::Unresolved,
+                        ))),
+                        args: vec![node(ExpressionStatement::Identifier(
+                            "def".to_string(),
+                            Binding::Unresolved,
+                        ))],
+                    },
+                )))],
+            },
+            Testcase {
+                name: "call expression followed by infix",
+                input: String::from("add(1, 2) + 3"),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Call {
+                            caller: Box::new(node(ExpressionStatement::Identifier(
+                                "add".to_string(),
+                                Binding::Unresolved,
+                            ))),
+                            args: vec![
+                                node(ExpressionStatement::Int(1)),
+                                node(ExpressionStatement::Int(2)),
+                            ],
+                        })),
+                        operator: Token::Plus,
+                        right: Box::new(node(ExpressionStatement::Int(3))),
+                    },
+                )))],
+            },
+            Testcase {
+                name: "array literal and index",
+                input: String::from("[1, a, ][-1]"),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Index {
+                        left: Box::new(node(ExpressionStatement::Array(vec![
+                            node(ExpressionStatement::Int(1)),
+                            node(ExpressionStatement::Identifier(
+                                "a".to_string(),
+                                Binding::Unresolved,
+                            )),
+                        ]))),
+                        index: Box::new(node(ExpressionStatement::Prefix {
+                            operator: Token::Minus,
+                            right: Box::new(node(ExpressionStatement::Int(1))),
+                        })),
+                    },
+                )))],
+            },
+            Testcase {
+                name: "slices with optional bounds",
+                input: String::from("a[1:]; a[:b + 1]; a[:]"),
+                expected: vec![
+                    node(Statement::Expression(node(ExpressionStatement::Slice {
+                        left: Box::new(node(ExpressionStatement::Identifier(
+                            "a".to_string(),
+                            Binding::Unresolved,
+                        ))),
+                        start: Some(Box::new(node(ExpressionStatement::Int(1)))),
+                        end: None,
+                    }))),
+                    node(Statement::Expression(node(ExpressionStatement::Slice {
+                        left: Box::new(node(ExpressionStatement::Identifier(
+                            "a".to_string(),
+                            Binding::Unresolved,
+                        ))),
+                        start: None,
+                        end: Some(Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Identifier(
+                                "b".to_string(),
+                                Binding::Unresolved,
+                            ))),
+                            operator: Token::Plus,
+                            right: Box::new(node(ExpressionStatement::Int(1))),
+                        }))),
+                    }))),
+                    node(Statement::Expression(node(ExpressionStatement::Slice {
+                        left: Box::new(node(ExpressionStatement::Identifier(
+                            "a".to_string(),
+                            Binding::Unresolved,
+                        ))),
+                        start: None,
+                        end: None,
+                    }))),
+                ],
+            },
+            Testcase {
+                name: "index assignment",
+                input: String::from("a[0] = b[1] = 2 + 3"),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Assign {
+                        target: Box::new(node(ExpressionStatement::Index {
+                            left: Box::new(node(ExpressionStatement::Identifier(
+                                "a".to_string(),
+                                Binding::Unresolved,
+                            ))),
+                            index: Box::new(node(ExpressionStatement::Int(0))),
+                        })),
+                        operator: None,
+                        value: Box::new(node(ExpressionStatement::Assign {
+                            target: Box::new(node(ExpressionStatement::Index {
+                                left: Box::new(node(ExpressionStatement::Identifier(
+                                    "b".to_string(),
+                                    Binding::Unresolved,
+                                ))),
+                                index: Box::new(node(ExpressionStatement::Int(1))),
+                            })),
+                            operator: None,
+                            value: Box::new(node(ExpressionStatement::Infix {
+                                left: Box::new(node(ExpressionStatement::Int(2))),
+                                operator: Token::Plus,
+                                right: Box::new(node(ExpressionStatement::Int(3))),
+                            })),
+                        })),
+                    },
+                )))],
+            },
+            Testcase {
+                name: "variable, field and compound assignment",
+                input: String::from("x = y.a -= 1; h.b.c *= 2"),
+                expected: vec![
+                    node(Statement::Expression(node(ExpressionStatement::Assign {
+                        target: Box::new(node(ExpressionStatement::Identifier(
+                            "x".to_string(),
+                            Binding::Unresolved,
+                        ))),
+                        operator: None,
+                        value: Box::new(node(ExpressionStatement::Assign {
+                            target: Box::new(node(ExpressionStatement::Field {
+                                left: Box::new(node(ExpressionStatement::Identifier(
+                                    "y".to_string(),
+                                    Binding::Unresolved,
+                                ))),
+                                name: "a".to_string(),
+                            })),
+                            operator: Some(Token::Minus),
+                            value: Box::new(node(ExpressionStatement::Int(1))),
+                        })),
+                    }))),
+                    node(Statement::Expression(node(ExpressionStatement::Assign {
+                        target: Box::new(node(ExpressionStatement::Field {
+                            left: Box::new(node(ExpressionStatement::Field {
+                                left: Box::new(node(ExpressionStatement::Identifier(
+                                    "h".to_string(),
+                                    Binding::Unresolved,
+                                ))),
+                                name: "b".to_string(),
+                            })),
+                            name: "c".to_string(),
+                        })),
+                        operator: Some(Token::Asterisk),
+                        value: Box::new(node(ExpressionStatement::Int(2))),
+                    }))),
+                ],
+            },
+            Testcase {
+                name: "hash literals",
+                input: String::from("{}; {\"a\": 1, 2: b,}"),
+                expected: vec![
+                    node(Statement::Expression(node(ExpressionStatement::Hash(
+                        vec![],
+                    )))),
+                    node(Statement::Expression(node(ExpressionStatement::Hash(
+                        vec![
+                            (
+                                node(ExpressionStatement::Str("a".to_string())),
+                                node(ExpressionStatement::Int(1)),
+                            ),
+                            (
+                                node(ExpressionStatement::Int(2)),
+                                node(ExpressionStatement::Identifier(
+                                    "b".to_string(),
+                                    Binding::Unresolved,
+                                )),
+                            ),
+                        ],
+                    )))),
+                ],
             },
             Testcase {
                 name: "group expression",
                 input: String::from("((def))"),
-                expected: vec![Statement::Expression(ExpressionStatement::Group(Box::new(
-                    ExpressionStatement::Group(Box::new(ExpressionStatement::Identifier(
-                        "def".to_string(),
-                    ))),
+                expected: vec![node(Statement::Expression(node(
+                    ExpressionStatement::Group(Box::new(node(ExpressionStatement::Group(
+                        Box::new(node(ExpressionStatement::Identifier(
+                            "def".to_string(),
+                            Binding::Unresolved,
+                        ))),
+                    )))),
                 )))],
             },
+            Testcase {
+                name: "while loop with break and continue",
+                input: String::from("while x < 1 { break; continue }"),
+                expected: vec![node(Statement::While {
+                    label: None,
+                    condition: node(ExpressionStatement::Infix {
+                        left: Box::new(node(ExpressionStatement::Identifier(
+                            "x".to_string(),
+                            Binding::Unresolved,
+                        ))),
+                        operator: Token::Lt,
+                        right: Box::new(node(ExpressionStatement::Int(1))),
+                    }),
+                    body: vec![
+                        node(Statement::Break(None)),
+                        node(Statement::Continue(None)),
+                    ],
+                })],
+            },
+            Testcase {
+                name: "labeled for loop over a range",
+                input: String::from(
+                    "outer: for i in 0..n + 1 {
+                        break outer
+                    }",
+                ),
+                expected: vec![node(Statement::For {
+                    label: Some("outer".to_string()),
+                    variable: "i".to_string(),
+                    iterable: node(ExpressionStatement::Range {
+                        start: Box::new(node(ExpressionStatement::Int(0))),
+                        end: Box::new(node(ExpressionStatement::Infix {
+                            left: Box::new(node(ExpressionStatement::Identifier(
+                                "n".to_string(),
+                                Binding::Unresolved,
+                            ))),
+                            operator: Token::Plus,
+                            right: Box::new(node(ExpressionStatement::Int(1))),
+                        })),
+                    }),
+                    body: vec![node(Statement::Break(Some("outer".to_string())))],
+                })],
+            },
+            Testcase {
+                name: "label is only taken from the same line",
+                input: String::from(
+                    "for x in xs { continue
+                        x }",
+                ),
+                expected: vec![node(Statement::For {
+                    label: None,
+                    variable: "x".to_string(),
+                    iterable: node(ExpressionStatement::Identifier(
+                        "xs".to_string(),
+                        Binding::Unresolved,
+                    )),
+                    body: vec![
+                        node(Statement::Continue(None)),
+                        node(Statement::Expression(node(
+                            ExpressionStatement::Identifier("x".to_string(), Binding::Unresolved),
+                        ))),
+                    ],
+                })],
+            },
         ];
         for testcase in testcases.into_iter() {
             let lexer = Lexer::new(&testcase.input);
             let mut parser = Parser::new(lexer);
-            let program = parser.parse_program().unwrap();
+            let (program, errors) = parser.parse_program();
+            assert!(errors.is_empty(), "{}: {:?}", testcase.name, errors);
             assert_eq!(program.statements.len(), testcase.expected.len());
             for (i, statement) in program.statements.into_iter().enumerate() {
                 assert_eq!(statement, testcase.expected[i]);
             }
         }
     }
+
+    #[test]
+    fn test_parser_errors() {
+        let testcases = vec![
+            (r#"let s = "abc"#, "Unterminated string literal"),
+            ("let = 5", "Expected Token::Ident, got Assign"),
+            (
+                "[1, 2",
+                "Expected Token::RSquareBracket or Token::Comma, got Eof",
+            ),
+            (
+                "a[1 2]",
+                "Expected Token::RSquareBracket or Token::Colon, got Int(2)",
+            ),
+            ("1 = 2", "Invalid assignment target Int(1)"),
+            ("a.1", "Expected Token::Ident, got Int(1)"),
+            ("let a = ;", "Expected an expression, got Semicolon"),
+            ("{1 \"abc}", "Unterminated string literal"),
+            ("{1 2}", "Expected Token::Colon, got Int(2)"),
+            (
+                "{1: 2 3}",
+                "Expected Token::RCurlyBracket or Token::Comma, got Int(3)",
+            ),
+            ("for 1 in a {}", "Expected Token::Ident, got Int(1)"),
+            ("for x a {}", "Expected Token::In, got Ident(\"a\")"),
+            ("while x 1", "Expected Token::LCurlyBracket, got Int(1)"),
+            ("a: 1", "Expected Token::While or Token::For, got Int(1)"),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            let mut parser = Parser::new(Lexer::new(input));
+            let (_, errors) = parser.parse_program();
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            assert_eq!(errors, vec![expected.to_string()], "{input}");
+        }
+    }
+
+    #[test]
+    fn test_parser_error_recovery() {
+        let input = "let = 1;
+            let a = 2;
+            let b = (3;
+            fn() { let = 4; 5 };
+            a + }
+            let c = 6";
+        let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+
+        let errors: Vec<(String, usize)> = errors
+            .iter()
+            .map(|e| (e.to_string(), e.span().unwrap().line))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("Expected Token::Ident, got Assign".to_string(), 1),
+                ("Expected Token::RParen, got Semicolon".to_string(), 3),
+                ("Expected Token::Ident, got Assign".to_string(), 4),
+                ("Expected an expression, got RCurlyBracket".to_string(), 5),
+            ]
+        );
+        assert_eq!(
+            program.statements,
+            vec![
+                node(Statement::Let(
+                    "a".to_string(),
+                    Some(node(ExpressionStatement::Int(2)))
+                )),
+                node(Statement::Expression(node(ExpressionStatement::Fn {
+                    args: vec![],
+                    body: vec![node(Statement::Expression(node(ExpressionStatement::Int(
+                        5
+                    ))))],
+                }))),
+                node(Statement::Let(
+                    "c".to_string(),
+                    Some(node(ExpressionStatement::Int(6)))
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parser_spans() {
+        let input = "let a = [1, 2];\nreturn f(a[0] + 1)";
+        let (program, _) = Parser::new(Lexer::new(input)).parse_program();
+        let source = |span: Span| &input[span.start..span.end];
+
+        assert_eq!(source(program.statements[0].span), "let a = [1, 2];");
+        assert_eq!(source(program.statements[1].span), "return f(a[0] + 1)");
+        let call = match &program.statements[1].node {
+            Statement::Return(Some(call)) => call,
+            statement => panic!("Expected return statement, got {:?}", statement),
+        };
+        assert_eq!((call.span.line, call.span.column), (2, 8));
+        let arg = match &call.node {
+            ExpressionStatement::Call { args, .. } => &args[0],
+            expr => panic!("Expected call expression, got {:?}", expr),
+        };
+        assert_eq!(source(arg.span), "a[0] + 1");
+    }
+
+    #[test]
+    fn test_parser_error_kinds() {
+        let mut parser = Parser::new(Lexer::new("let 5"));
+        assert_eq!(
+            parser.parse_program().1,
+            vec![InterpreterError::Parse(
+                ParseError::UnexpectedToken {
+                    expected: "Token::Ident".to_string(),
+                    found: Token::Int(5),
+                },
+                Span {
+                    start: 4,
+                    end: 5,
+                    line: 1,
+                    column: 5,
+                },
+            )]
+        );
+
+        let mut parser = Parser::new(Lexer::new("1 + @"));
+        assert!(matches!(
+            parser.parse_program().1[..],
+            [InterpreterError::Lex(LexError::IllegalCharacter('@'), _)]
+        ));
+    }
+
+    #[test]
+    fn test_parser_error_spans() {
+        let testcases = vec![
+            ("let = 5", (1, 5)),
+            ("let a = [1,\n  2", (2, 4)),
+            ("let a = 1;\n2 = 2", (2, 1)),
+            ("{1 2}", (1, 4)),
+            ("\"abc", (1, 1)),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            let mut parser = Parser::new(Lexer::new(input));
+            let span = parser.parse_program().1.first().and_then(|e| e.span());
+            assert_eq!(
+                span.map(|span| (span.line, span.column)),
+                Some(expected),
+                "{input}"
+            );
+        }
+    }
 }
@@ -0,0 +1,564 @@
+use crate::error::{InterpreterError, RuntimeError};
+use crate::object::Environment;
+use crate::parser::Program;
+use crate::span::{Span, Spanned};
+use crate::statement::{Binding, ExpressionStatement, Statement};
+
+use std::collections::HashSet;
+use std::mem;
+
+struct Resolver<'a> {
+    // Environment the program will run in, with the globals defined so far
+    // and the builtins. The globals the program uses are numbered in it
+    globals: &'a mut Environment,
+    // Every top level variable of the program, functions can refer to the
+    // ones declared after them
+    program_globals: HashSet<String>,
+    // Top level variables declared before the statement being resolved
+    declared_globals: HashSet<String>,
+    // Names of the function and block scopes around the code being resolved,
+    // innermost last, in slot order
+    scopes: Vec<Vec<String>>,
+    // Number of functions the code being resolved is nested in
+    functions: usize,
+    // Labels of the loops around the code being resolved in the innermost
+    // function, innermost last. A function body starts outside any loop
+    loops: Vec<Option<String>>,
+    errors: Vec<InterpreterError>,
+}
+
+// Check program before it runs in globals, and bind every identifier of a
+// function or block scope to its slot. Reports every use of an undeclared
+// name, duplicate let in one scope, return outside a function and break or
+// continue outside a loop, together like syntax errors. Top level variables
+// stay globals, bound to their number in globals, so functions can use the
+// ones declared later and every source run in the environment sees the
+// earlier ones
+pub fn resolve(program: &mut Program, globals: &mut Environment) -> Result<(), InterpreterError> {
+    let program_globals = program
+        .statements
+        .iter()
+        .filter_map(|statement| match &statement.node {
+            Statement::Let(name, _) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut resolver = Resolver {
+        globals,
+        program_globals,
+        declared_globals: HashSet::new(),
+        scopes: vec![],
+        functions: 0,
+        loops: vec![],
+        errors: vec![],
+    };
+    resolver.statements(&mut program.statements);
+    if !resolver.errors.is_empty() {
+        return Err(InterpreterError::Syntax(resolver.errors));
+    }
+    Ok(())
+}
+
+impl Resolver<'_> {
+    fn error(&mut self, e: RuntimeError, span: Span) {
+        self.errors.push(InterpreterError::Resolve(e, span));
+    }
+
+    // Local variable name refers to, from the innermost scope out
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let slot = scope.iter().rposition(|local| local == name)?;
+                Some(Binding::Local { depth, slot })
+            })
+    }
+
+    // Whether name is a global variable where it is used. Top level code only
+    // sees the ones declared before it
+    fn is_global(&self, name: &str) -> bool {
+        let declared = match self.functions {
+            0 => &self.declared_globals,
+            _ => &self.program_globals,
+        };
+        declared.contains(name) || self.globals.variables.contains_key(name)
+    }
+
+    fn declare(&mut self, name: &str) {
+        self.scopes
+            .last_mut()
+            .expect("scope")
+            .push(name.to_string());
+    }
+
+    // Statements of a new block scope
+    fn block(&mut self, block: &mut [Spanned<Statement>], scope: Vec<String>) {
+        self.scopes.push(scope);
+        self.statements(block);
+        self.scopes.pop();
+    }
+
+    // Body of a loop with label
+    fn loop_body(
+        &mut self,
+        label: &Option<String>,
+        body: &mut [Spanned<Statement>],
+        scope: Vec<String>,
+    ) {
+        self.loops.push(label.clone());
+        self.block(body, scope);
+        self.loops.pop();
+    }
+
+    fn statements(&mut self, statements: &mut [Spanned<Statement>]) {
+        for statement in statements.iter_mut() {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &mut Spanned<Statement>) {
+        let span = statement.span;
+        match &mut statement.node {
+            Statement::Let(name, value) => self.let_statement(name, value, span),
+            Statement::Return(value) => {
+                if self.functions == 0 {
+                    self.error(RuntimeError::ReturnOutsideFunction, span);
+                }
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Statement::Expression(expr) => self.expression(expr),
+            Statement::While {
+                label,
+                condition,
+                body,
+            } => {
+                self.expression(condition);
+                self.loop_body(label, body, vec![]);
+            }
+            Statement::For {
+                label,
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.loop_body(label, body, vec![variable.clone()]);
+            }
+            Statement::Break(label) => self.jump_out(label, "break", span),
+            Statement::Continue(label) => self.jump_out(label, "continue", span),
+        }
+    }
+
+    // break or continue, by keyword, must be inside the loop it leaves
+    fn jump_out(&mut self, label: &Option<String>, keyword: &'static str, span: Span) {
+        match label {
+            None if self.loops.is_empty() => self.error(RuntimeError::OutsideLoop(keyword), span),
+            Some(label) if !self.loops.iter().any(|lp| lp.as_ref() == Some(label)) => {
+                self.error(RuntimeError::UndefinedLabel(label.clone()), span)
+            }
+            _ => {}
+        }
+    }
+
+    fn let_statement(
+        &mut self,
+        name: &str,
+        value: &mut Option<Spanned<ExpressionStatement>>,
+        span: Span,
+    ) {
+        let already_declared = match self.scopes.last() {
+            None => {
+                self.declared_globals.contains(name) || self.globals.variables.contains_key(name)
+            }
+            Some(scope) => scope.iter().any(|local| local == name),
+        };
+        if already_declared {
+            self.error(RuntimeError::AlreadyDeclared(name.to_string()), span);
+        }
+
+        if self.scopes.is_empty() {
+            if let Some(value) = value {
+                self.expression(value);
+            }
+            self.declared_globals.insert(name.to_string());
+            return;
+        }
+
+        // A function literal can refer to the variable it is bound to, so
+        // local functions can be recursive. Any other value sees the outer
+        // variable of the same name, if there is one
+        match value {
+            Some(value) if matches!(value.node, ExpressionStatement::Fn { .. }) => {
+                self.declare(name);
+                self.expression(value);
+            }
+            _ => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                self.declare(name);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Spanned<ExpressionStatement>) {
+        let span = expr.span;
+        match &mut expr.node {
+            ExpressionStatement::Prefix { right, .. } => self.expression(right),
+            ExpressionStatement::Infix { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExpressionStatement::If {
+                condition,
+                outcome,
+                alternate,
+            } => {
+                self.expression(condition);
+                self.block(outcome, vec![]);
+                self.block(alternate, vec![]);
+            }
+            ExpressionStatement::Fn { args, body } => {
+                self.functions += 1;
+                let loops = mem::take(&mut self.loops);
+                // Parameters and the variables of the body share one scope
+                self.block(body, args.clone());
+                self.loops = loops;
+                self.functions -= 1;
+            }
+            ExpressionStatement::Call { caller, args } => {
+                self.expression(caller);
+                self.list(args);
+            }
+            ExpressionStatement::Index { left, index } => {
+                self.expression(left);
+                self.expression(index);
+            }
+            ExpressionStatement::Slice { left, start, end } => {
+                self.expression(left);
+                if let Some(start) = start {
+                    self.expression(start);
+                }
+                if let Some(end) = end {
+                    self.expression(end);
+                }
+            }
+            ExpressionStatement::Field { left, .. } => self.expression(left),
+            ExpressionStatement::Assign {
+                target,
+                operator,
+                value,
+            } => {
+                match &mut target.node {
+                    ExpressionStatement::Identifier(name, binding) => {
+                        self.assign_target(name, binding, operator.is_some(), span)
+                    }
+                    _ => self.expression(target),
+                }
+                self.expression(value);
+            }
+            ExpressionStatement::Range { start, end } => {
+                self.expression(start);
+                self.expression(end);
+            }
+            ExpressionStatement::Group(expr) => self.expression(expr),
+            ExpressionStatement::Array(elements) => self.list(elements),
+            ExpressionStatement::Hash(pairs) => {
+                for (key, value) in pairs.iter_mut() {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            ExpressionStatement::Identifier(name, binding) => match self.lookup(name) {
+                Some(local) => *binding = local,
+                None if self.is_global(name) || self.globals.builtins.contains_key(name) => {
+                    *binding = Binding::Global(self.globals.variables.index(name))
+                }
+                None => self.error(RuntimeError::UndefinedVariable(name.clone()), span),
+            },
+            ExpressionStatement::Int(_)
+            | ExpressionStatement::Num(_)
+            | ExpressionStatement::Str(_)
+            | ExpressionStatement::Bool(_) => {}
+        }
+    }
+
+    // Variable assigned to by the assignment at span. Builtins can be read by
+    // a compound assignment, but not assigned
+    fn assign_target(&mut self, name: &str, binding: &mut Binding, compound: bool, span: Span) {
+        if let Some(local) = self.lookup(name) {
+            *binding = local;
+            return;
+        }
+        if self.is_global(name) {
+            *binding = Binding::Global(self.globals.variables.index(name));
+            return;
+        }
+        let e = if compound && !self.globals.builtins.contains_key(name) {
+            RuntimeError::UndefinedVariable(name.to_string())
+        } else {
+            RuntimeError::UndeclaredAssignment(name.to_string())
+        };
+        self.error(e, span);
+    }
+
+    fn list(&mut self, list: &mut [Spanned<ExpressionStatement>]) {
+        for expr in list.iter_mut() {
+            self.expression(expr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::object::Object;
+    use crate::parser::Parser;
+
+    fn resolved(input: &str, globals: &mut Environment) -> Result<Program, InterpreterError> {
+        let (mut program, errors) = Parser::new(Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "{input}: {:?}", errors);
+        resolve(&mut program, globals)?;
+        Ok(program)
+    }
+
+    // Name and binding of every identifier, in source order
+    fn bindings(statements: &[Spanned<Statement>]) -> Vec<(String, Binding)> {
+        fn expression(expr: &ExpressionStatement, found: &mut Vec<(String, Binding)>) {
+            match expr {
+                ExpressionStatement::Identifier(name, binding) => {
+                    found.push((name.clone(), *binding))
+                }
+                ExpressionStatement::Infix { left, right, .. } => {
+                    expression(&left.node, found);
+                    expression(&right.node, found);
+                }
+                ExpressionStatement::Call { caller, args } => {
+                    expression(&caller.node, found);
+                    args.iter().for_each(|arg| expression(&arg.node, found));
+                }
+                ExpressionStatement::Assign { target, value, .. } => {
+                    expression(&target.node, found);
+                    expression(&value.node, found);
+                }
+                ExpressionStatement::Fn { body, .. } => found.extend(bindings(body)),
+                ExpressionStatement::If {
+                    condition,
+                    outcome,
+                    alternate,
+                } => {
+                    expression(&condition.node, found);
+                    found.extend(bindings(outcome));
+                    found.extend(bindings(alternate));
+                }
+                _ => {}
+            }
+        }
+
+        let mut found = vec![];
+        for statement in statements {
+            match &statement.node {
+                Statement::Let(_, Some(expr))
+                | Statement::Return(Some(expr))
+                | Statement::Expression(expr) => expression(&expr.node, &mut found),
+                Statement::For { iterable, body, .. } => {
+                    expression(&iterable.node, &mut found);
+                    found.extend(bindings(body));
+                }
+                Statement::While {
+                    condition, body, ..
+                } => {
+                    expression(&condition.node, &mut found);
+                    found.extend(bindings(body));
+                }
+                _ => {}
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn test_resolve() {
+        let local = |depth, slot| Binding::Local { depth, slot };
+        let testcases = vec![
+            (
+                "let g = 1; let f = fn(a, b) { let c = a; fn() { b + c + g } }",
+                vec![
+                    ("a", local(0, 0)),
+                    ("b", local(1, 1)),
+                    ("c", local(1, 2)),
+                    ("g", Binding::Global(0)),
+                ],
+            ),
+            (
+                "let f = fn(n) { let fact = fn(n) { if n { fact(n - 1) } }; fact(n) }",
+                vec![
+                    ("n", local(0, 0)),
+                    ("fact", local(2, 1)),
+                    ("n", local(1, 0)),
+                    ("fact", local(0, 1)),
+                    ("n", local(0, 0)),
+                ],
+            ),
+            (
+                "let x = 1; if true { let x = x; x = 2 }; for i in 0..2 { while i { x += i } }",
+                vec![
+                    ("x", Binding::Global(0)),
+                    ("x", local(0, 0)),
+                    ("i", local(0, 0)),
+                    ("x", Binding::Global(0)),
+                    ("i", local(1, 0)),
+                ],
+            ),
+            (
+                "let f = fn() { later() + len([]) }; let later = fn() { 1 }",
+                vec![("later", Binding::Global(0)), ("len", Binding::Global(1))],
+            ),
+        ];
+
+        for (input, expected) in testcases.into_iter() {
+            // Globals are numbered in the order they are first used
+            let globals = Environment::new(None);
+            let program = resolved(input, &mut globals.borrow_mut()).unwrap();
+            let expected: Vec<(String, Binding)> = expected
+                .into_iter()
+                .map(|(name, binding)| (name.to_string(), binding))
+                .collect();
+            assert_eq!(bindings(&program.statements), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let testcases = vec![
+            (
+                "1 + missing",
+                RuntimeError::UndefinedVariable("missing".to_string()),
+                (1, 5),
+            ),
+            (
+                "later; let later = 1",
+                RuntimeError::UndefinedVariable("later".to_string()),
+                (1, 1),
+            ),
+            (
+                "let f = fn() { let g = fn() { x }; let x = 1; g() }",
+                RuntimeError::UndefinedVariable("x".to_string()),
+                (1, 31),
+            ),
+            (
+                "if true { let a = 1 }; a",
+                RuntimeError::UndefinedVariable("a".to_string()),
+                (1, 24),
+            ),
+            (
+                "let a = 1;\nlet a = 2",
+                RuntimeError::AlreadyDeclared("a".to_string()),
+                (2, 1),
+            ),
+            (
+                "let f = fn(x) {\n  let x = 1\n}",
+                RuntimeError::AlreadyDeclared("x".to_string()),
+                (2, 3),
+            ),
+            (
+                "let defined = 2",
+                RuntimeError::AlreadyDeclared("defined".to_string()),
+                (1, 1),
+            ),
+            (
+                "x = 1",
+                RuntimeError::UndeclaredAssignment("x".to_string()),
+                (1, 1),
+            ),
+            (
+                "len = 1",
+                RuntimeError::UndeclaredAssignment("len".to_string()),
+                (1, 1),
+            ),
+            (
+                "fn() { x += 1 }",
+                RuntimeError::UndefinedVariable("x".to_string()),
+                (1, 8),
+            ),
+            (
+                "if true {\n  return 1\n}",
+                RuntimeError::ReturnOutsideFunction,
+                (2, 3),
+            ),
+            (
+                "if false { break }",
+                RuntimeError::OutsideLoop("break"),
+                (1, 12),
+            ),
+            (
+                "for i in 0..1 { let f = fn() { continue } }",
+                RuntimeError::OutsideLoop("continue"),
+                (1, 32),
+            ),
+            (
+                "outer: while true { for i in 0..1 { break inner } }",
+                RuntimeError::UndefinedLabel("inner".to_string()),
+                (1, 37),
+            ),
+        ];
+
+        let globals = Environment::new(None);
+        globals
+            .borrow_mut()
+            .variables
+            .insert("defined".to_string(), Object::Null);
+        for (input, expected, (line, column)) in testcases.into_iter() {
+            match resolved(input, &mut globals.borrow_mut()) {
+                Err(InterpreterError::Syntax(errors)) => match &errors[..] {
+                    [InterpreterError::Resolve(e, span)] => {
+                        assert_eq!(*e, expected, "{input}");
+                        assert_eq!((span.line, span.column), (line, column), "{input}");
+                    }
+                    errors => panic!("Expected one error for {input}, got {:?}", errors),
+                },
+                result => panic!("Expected error for {input}, got {:?}", result.map(|_| ())),
+            }
+        }
+
+        // Globals of the environment are known
+        assert!(resolved("defined + len(\"\")", &mut globals.borrow_mut()).is_ok());
+        assert!(resolved(
+            "outer: for i in 0..1 { while true { break outer } }",
+            &mut globals.borrow_mut()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_resolve_reports_every_error() {
+        let input = "let a = missing;
+let a = 1;
+let f = fn() { break; a = b }";
+        let errors = match resolved(input, &mut Environment::new(None).borrow_mut()) {
+            Err(InterpreterError::Syntax(errors)) => errors,
+            result => panic!("Expected errors, got {:?}", result.map(|_| ())),
+        };
+        let errors: Vec<(String, (usize, usize))> = errors
+            .iter()
+            .map(|e| {
+                let span = e.span().expect("span");
+                (e.to_string(), (span.line, span.column))
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("undefined variable \"missing\"".to_string(), (1, 9)),
+                ("a is initialized".to_string(), (2, 1)),
+                ("'break' outside loop".to_string(), (3, 16)),
+                ("undefined variable \"b\"".to_string(), (3, 27)),
+            ]
+        );
+    }
+}
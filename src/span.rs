@@ -0,0 +1,96 @@
+// Location of a token or AST node in the source. start and end are byte offsets
+// (end is exclusive), line and column are 1-based and point at start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // Span covering self up to the end of other
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    // Render the source line the span starts on with a caret underline, e.g.
+    //   2 | let b = a / 0
+    //     |         ^^^^^
+    // Spans running past the end of the line are underlined up to the line end
+    pub fn render(&self, source: &str) -> String {
+        let line = source.lines().nth(self.line - 1).unwrap_or("");
+        let line_start = source[..self.start.min(source.len())]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_end = line_start + line.len();
+
+        let underline_start = line[..self.start.clamp(line_start, line_end) - line_start]
+            .chars()
+            .count();
+        let underline_len = source
+            .get(self.start.min(line_end)..self.end.clamp(self.start, line_end))
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+
+        let gutter = " ".repeat(self.line.to_string().len());
+        format!(
+            "{} | {}\n{} | {}{}",
+            self.line,
+            line,
+            gutter,
+            " ".repeat(underline_start),
+            "^".repeat(underline_len)
+        )
+    }
+}
+
+// AST node together with the span of source it was parsed from. Spans are
+// ignored when comparing nodes, so the same code parsed from differently laid
+// out source compares equal
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "let a = 1;\nlet b = a / 0\n";
+        let span = Span {
+            start: 19,
+            end: 24,
+            line: 2,
+            column: 9,
+        };
+        assert_eq!(span.render(source), "2 | let b = a / 0\n  |         ^^^^^");
+
+        // Empty spans such as the end of input still get a caret
+        let span = Span {
+            start: 10,
+            end: 10,
+            line: 1,
+            column: 11,
+        };
+        assert_eq!(span.render(source), "1 | let a = 1;\n  |           ^");
+    }
+}
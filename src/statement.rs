@@ -1,38 +1,686 @@
-use crate::token::Token;
+use crate::parser::{
+    Parser, Precedence, PRECEDENCE_ASSIGN, PRECEDENCE_LOWEST, PRECEDENCE_PARENTHESE,
+    PRECEDENCE_PREFIX, PRECEDENCE_RANGE,
+};
+use crate::span::Spanned;
+use crate::token::{self, Token};
 
-#[derive(Debug, PartialEq)]
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    Let(String, Option<ExpressionStatement>),
-    Return(Option<ExpressionStatement>),
-    Expression(ExpressionStatement),
+    Let(String, Option<Spanned<ExpressionStatement>>),
+    Return(Option<Spanned<ExpressionStatement>>),
+    Expression(Spanned<ExpressionStatement>),
+    // label names the loop for a break or continue in a nested loop
+    While {
+        label: Option<String>,
+        condition: Spanned<ExpressionStatement>,
+        body: Vec<Spanned<Statement>>,
+    },
+    For {
+        label: Option<String>,
+        variable: String,
+        iterable: Spanned<ExpressionStatement>,
+        body: Vec<Spanned<Statement>>,
+    },
+    Break(Option<String>),
+    Continue(Option<String>),
 }
 
-#[derive(Debug, PartialEq)]
+// Where the variable an identifier names lives, filled in by the resolver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Binding {
+    // As parsed, before the resolver has seen it
+    #[default]
+    Unresolved,
+    // Global variable numbered index in the global environment, or the
+    // builtin of its name
+    Global(usize),
+    // Slot of the function or block scope depth scopes out from the use
+    Local {
+        depth: usize,
+        slot: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExpressionStatement {
     Prefix {
         operator: Token,
-        right: Box<ExpressionStatement>,
+        right: Box<Spanned<ExpressionStatement>>,
     },
     Infix {
-        left: Box<ExpressionStatement>,
+        left: Box<Spanned<ExpressionStatement>>,
         operator: Token,
-        right: Box<ExpressionStatement>,
+        right: Box<Spanned<ExpressionStatement>>,
     },
     If {
-        condition: Box<ExpressionStatement>,
-        outcome: Vec<Statement>,
-        alternate: Vec<Statement>,
+        condition: Box<Spanned<ExpressionStatement>>,
+        outcome: Vec<Spanned<Statement>>,
+        alternate: Vec<Spanned<Statement>>,
     },
     Fn {
         args: Vec<String>,
-        body: Vec<Statement>,
+        body: Vec<Spanned<Statement>>,
     },
     Call {
-        caller: Box<ExpressionStatement>,
-        args: Vec<ExpressionStatement>,
+        caller: Box<Spanned<ExpressionStatement>>,
+        args: Vec<Spanned<ExpressionStatement>>,
+    },
+    Index {
+        left: Box<Spanned<ExpressionStatement>>,
+        index: Box<Spanned<ExpressionStatement>>,
     },
-    Group(Box<ExpressionStatement>),
-    Identifier(String),
+    Slice {
+        left: Box<Spanned<ExpressionStatement>>,
+        start: Option<Box<Spanned<ExpressionStatement>>>,
+        end: Option<Box<Spanned<ExpressionStatement>>>,
+    },
+    Field {
+        left: Box<Spanned<ExpressionStatement>>,
+        name: String,
+    },
+    // operator is the binary operator of a compound assignment such as +=
+    Assign {
+        target: Box<Spanned<ExpressionStatement>>,
+        operator: Option<Token>,
+        value: Box<Spanned<ExpressionStatement>>,
+    },
+    // start..end, end excluded
+    Range {
+        start: Box<Spanned<ExpressionStatement>>,
+        end: Box<Spanned<ExpressionStatement>>,
+    },
+    Group(Box<Spanned<ExpressionStatement>>),
+    Array(Vec<Spanned<ExpressionStatement>>),
+    Hash(Vec<(Spanned<ExpressionStatement>, Spanned<ExpressionStatement>)>),
+    Identifier(String, Binding),
+    Int(i64),
     Num(f64),
+    Str(String),
     Bool(bool),
 }
+
+const INDENT: &str = "    ";
+
+// Canonical source of the statement, blocks in it are indented by four spaces
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_statement(f, self, 0)
+    }
+}
+
+// Canonical source of the expression, parenthesized only where precedence
+// requires it
+impl fmt::Display for ExpressionStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_expression(f, self, 0)
+    }
+}
+
+// Statements one per line at the given indentation. Every statement is ended
+// with a semicolon except loops, the final expression (the value of the block)
+// and if and fn expressions ending in a bracket, which only need one when the
+// next statement would otherwise continue them, e.g. as a call. Between top
+// level statements spanning several lines there is an empty line
+pub(crate) fn write_statements(
+    f: &mut fmt::Formatter<'_>,
+    statements: &[Spanned<Statement>],
+    indent: usize,
+    top_level: bool,
+) -> fmt::Result {
+    for (i, statement) in statements.iter().enumerate() {
+        if top_level && i > 0 {
+            let previous = &statements[i - 1].node;
+            if is_multiline(previous) || is_multiline(&statement.node) {
+                writeln!(f)?;
+            }
+        }
+        write!(f, "{}", INDENT.repeat(indent))?;
+        write_statement(f, &statement.node, indent)?;
+        let next = statements.get(i + 1).map(|next| &next.node);
+        if needs_semicolon(&statement.node, next) {
+            write!(f, ";")?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+fn is_multiline(statement: &Statement) -> bool {
+    statement.to_string().contains('\n')
+}
+
+fn needs_semicolon(statement: &Statement, next: Option<&Statement>) -> bool {
+    match (statement, next) {
+        (Statement::While { .. } | Statement::For { .. }, _) => false,
+        (Statement::Expression(_), None) => false,
+        (Statement::Expression(expr), Some(next))
+            if matches!(
+                ungroup(&expr.node),
+                ExpressionStatement::If { .. } | ExpressionStatement::Fn { .. }
+            ) =>
+        {
+            next.to_string().starts_with(['(', '[', '-'])
+        }
+        _ => true,
+    }
+}
+
+fn write_block(
+    f: &mut fmt::Formatter<'_>,
+    block: &[Spanned<Statement>],
+    indent: usize,
+) -> fmt::Result {
+    if block.is_empty() {
+        return write!(f, "{{}}");
+    }
+    writeln!(f, "{{")?;
+    write_statements(f, block, indent + 1, false)?;
+    write!(f, "{}}}", INDENT.repeat(indent))
+}
+
+fn write_label(f: &mut fmt::Formatter<'_>, label: &Option<String>) -> fmt::Result {
+    match label {
+        Some(label) => write!(f, "{}: ", label),
+        None => Ok(()),
+    }
+}
+
+fn write_statement(
+    f: &mut fmt::Formatter<'_>,
+    statement: &Statement,
+    indent: usize,
+) -> fmt::Result {
+    match statement {
+        Statement::Let(name, None) => write!(f, "let {}", name),
+        Statement::Let(name, Some(value)) => {
+            write!(f, "let {} = ", name)?;
+            write_expression(f, &value.node, indent)
+        }
+        Statement::Return(None) => write!(f, "return"),
+        Statement::Return(Some(value)) => {
+            write!(f, "return ")?;
+            write_expression(f, &value.node, indent)
+        }
+        Statement::Expression(expr) => write_expression(f, &expr.node, indent),
+        Statement::While {
+            label,
+            condition,
+            body,
+        } => {
+            write_label(f, label)?;
+            write!(f, "while ")?;
+            write_expression(f, &condition.node, indent)?;
+            write!(f, " ")?;
+            write_block(f, body, indent)
+        }
+        Statement::For {
+            label,
+            variable,
+            iterable,
+            body,
+        } => {
+            write_label(f, label)?;
+            write!(f, "for {} in ", variable)?;
+            write_expression(f, &iterable.node, indent)?;
+            write!(f, " ")?;
+            write_block(f, body, indent)
+        }
+        Statement::Break(None) => write!(f, "break"),
+        Statement::Break(Some(label)) => write!(f, "break {}", label),
+        Statement::Continue(None) => write!(f, "continue"),
+        Statement::Continue(Some(label)) => write!(f, "continue {}", label),
+    }
+}
+
+// Expression a group stands for, its parentheses are only printed again if
+// precedence requires them
+fn ungroup(mut expr: &ExpressionStatement) -> &ExpressionStatement {
+    while let ExpressionStatement::Group(inner) = expr {
+        expr = &inner.node;
+    }
+    expr
+}
+
+// How tightly the printed expression binds, as the parser's precedences. Calls,
+// indexing and fields chain with each other whatever their precedence, so
+// they share one level
+fn precedence(expr: &ExpressionStatement) -> Precedence {
+    match expr {
+        ExpressionStatement::Assign { .. } => PRECEDENCE_ASSIGN,
+        ExpressionStatement::Infix { operator, .. } => Parser::get_precedence(operator),
+        ExpressionStatement::Range { .. } => PRECEDENCE_RANGE,
+        ExpressionStatement::Prefix { .. } => PRECEDENCE_PREFIX,
+        ExpressionStatement::Call { .. }
+        | ExpressionStatement::Index { .. }
+        | ExpressionStatement::Slice { .. }
+        | ExpressionStatement::Field { .. } => PRECEDENCE_PARENTHESE,
+        _ => Precedence::MAX,
+    }
+}
+
+// Operand on the left of an operator, it is parsed first and extended by any
+// operator binding at least as tight as min
+fn write_left(
+    f: &mut fmt::Formatter<'_>,
+    expr: &ExpressionStatement,
+    min: Precedence,
+    indent: usize,
+) -> fmt::Result {
+    let expr = ungroup(expr);
+    write_operand(f, expr, precedence(expr) < min, indent)
+}
+
+// Operand on the right of an operator, it is parsed only taking operators
+// binding tighter than min. Prefix expressions and anything tighter are
+// complete on their own
+fn write_right(
+    f: &mut fmt::Formatter<'_>,
+    expr: &ExpressionStatement,
+    min: Precedence,
+    indent: usize,
+) -> fmt::Result {
+    let expr = ungroup(expr);
+    let binary = matches!(
+        expr,
+        ExpressionStatement::Assign { .. }
+            | ExpressionStatement::Infix { .. }
+            | ExpressionStatement::Range { .. }
+    );
+    write_operand(f, expr, binary && precedence(expr) <= min, indent)
+}
+
+fn write_operand(
+    f: &mut fmt::Formatter<'_>,
+    expr: &ExpressionStatement,
+    parenthesize: bool,
+    indent: usize,
+) -> fmt::Result {
+    if parenthesize {
+        write!(f, "(")?;
+        write_expression(f, expr, indent)?;
+        return write!(f, ")");
+    }
+    write_expression(f, expr, indent)
+}
+
+fn write_list(
+    f: &mut fmt::Formatter<'_>,
+    list: &[Spanned<ExpressionStatement>],
+    indent: usize,
+) -> fmt::Result {
+    for (i, expr) in list.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write_expression(f, &expr.node, indent)?;
+    }
+    Ok(())
+}
+
+fn write_expression(
+    f: &mut fmt::Formatter<'_>,
+    expr: &ExpressionStatement,
+    indent: usize,
+) -> fmt::Result {
+    match expr {
+        ExpressionStatement::Prefix { operator, right } => {
+            write!(f, "{}", operator)?;
+            write_right(f, &right.node, PRECEDENCE_PREFIX, indent)
+        }
+        ExpressionStatement::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            let precedence = Parser::get_precedence(operator);
+            // ** is right associative
+            let (left_min, right_min) = match operator {
+                Token::DoubleAsterisk => (precedence + 1, precedence - 1),
+                _ => (precedence, precedence),
+            };
+            write_left(f, &left.node, left_min, indent)?;
+            write!(f, " {} ", operator)?;
+            write_right(f, &right.node, right_min, indent)
+        }
+        ExpressionStatement::If {
+            condition,
+            outcome,
+            alternate,
+        } => {
+            // The parser takes a parenthesis right after if as the start of
+            // `if (condition)`, so a condition starting with one is wrapped
+            let condition = condition.node.to_string();
+            let condition = condition.replace('\n', &format!("\n{}", INDENT.repeat(indent)));
+            if condition.starts_with('(') {
+                write!(f, "if ({}) ", condition)?;
+            } else {
+                write!(f, "if {} ", condition)?;
+            }
+            write_block(f, outcome, indent)?;
+            match alternate.as_slice() {
+                [] => Ok(()),
+                [Spanned {
+                    node: Statement::Expression(expr),
+                    ..
+                }] if matches!(expr.node, ExpressionStatement::If { .. }) => {
+                    write!(f, " else ")?;
+                    write_expression(f, &expr.node, indent)
+                }
+                _ => {
+                    write!(f, " else ")?;
+                    write_block(f, alternate, indent)
+                }
+            }
+        }
+        ExpressionStatement::Fn { args, body } => {
+            write!(f, "fn({}) ", args.join(", "))?;
+            write_block(f, body, indent)
+        }
+        ExpressionStatement::Call { caller, args } => {
+            write_left(f, &caller.node, PRECEDENCE_PARENTHESE, indent)?;
+            write!(f, "(")?;
+            write_list(f, args, indent)?;
+            write!(f, ")")
+        }
+        ExpressionStatement::Index { left, index } => {
+            write_left(f, &left.node, PRECEDENCE_PARENTHESE, indent)?;
+            write!(f, "[")?;
+            write_expression(f, &index.node, indent)?;
+            write!(f, "]")
+        }
+        ExpressionStatement::Slice { left, start, end } => {
+            write_left(f, &left.node, PRECEDENCE_PARENTHESE, indent)?;
+            write!(f, "[")?;
+            if let Some(start) = start {
+                write_expression(f, &start.node, indent)?;
+            }
+            write!(f, ":")?;
+            if let Some(end) = end {
+                write_expression(f, &end.node, indent)?;
+            }
+            write!(f, "]")
+        }
+        ExpressionStatement::Field { left, name } => {
+            write_left(f, &left.node, PRECEDENCE_PARENTHESE, indent)?;
+            write!(f, ".{}", name)
+        }
+        ExpressionStatement::Assign {
+            target,
+            operator,
+            value,
+        } => {
+            write_left(f, &target.node, PRECEDENCE_PARENTHESE, indent)?;
+            match operator {
+                Some(operator) => write!(f, " {}= ", operator)?,
+                None => write!(f, " = ")?,
+            }
+            write_right(f, &value.node, PRECEDENCE_LOWEST, indent)
+        }
+        ExpressionStatement::Range { start, end } => {
+            write_left(f, &start.node, PRECEDENCE_RANGE, indent)?;
+            write!(f, "..")?;
+            write_right(f, &end.node, PRECEDENCE_RANGE, indent)
+        }
+        ExpressionStatement::Group(expr) => write_expression(f, &expr.node, indent),
+        ExpressionStatement::Array(elements) => {
+            write!(f, "[")?;
+            write_list(f, elements, indent)?;
+            write!(f, "]")
+        }
+        ExpressionStatement::Hash(pairs) => {
+            write!(f, "{{")?;
+            for (i, (key, value)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_expression(f, &key.node, indent)?;
+                write!(f, ": ")?;
+                write_expression(f, &value.node, indent)?;
+            }
+            write!(f, "}}")
+        }
+        ExpressionStatement::Identifier(name, _) => write!(f, "{}", name),
+        ExpressionStatement::Int(num) => write!(f, "{}", num),
+        ExpressionStatement::Num(num) => write!(f, "{}", token::number_literal(*num)),
+        ExpressionStatement::Str(s) => write!(f, "{}", token::string_literal(s)),
+        ExpressionStatement::Bool(b) => write!(f, "{}", b),
+    }
+}
+
+// Replace every Group in the statements by the expression in it
+pub(crate) fn ungroup_statements(statements: &mut [Spanned<Statement>]) {
+    for statement in statements.iter_mut() {
+        match &mut statement.node {
+            Statement::Let(_, value) | Statement::Return(value) => {
+                value.iter_mut().for_each(ungroup_expression)
+            }
+            Statement::Expression(expr) => ungroup_expression(expr),
+            Statement::While {
+                condition: expr,
+                body,
+                ..
+            }
+            | Statement::For {
+                iterable: expr,
+                body,
+                ..
+            } => {
+                ungroup_expression(expr);
+                ungroup_statements(body);
+            }
+            Statement::Break(_) | Statement::Continue(_) => {}
+        }
+    }
+}
+
+fn ungroup_expression(expr: &mut Spanned<ExpressionStatement>) {
+    if let ExpressionStatement::Group(inner) = &mut expr.node {
+        let inner = std::mem::replace(&mut inner.node, ExpressionStatement::Bool(false));
+        expr.node = inner;
+        return ungroup_expression(expr);
+    }
+    match &mut expr.node {
+        ExpressionStatement::Prefix { right: expr, .. }
+        | ExpressionStatement::Field { left: expr, .. } => ungroup_expression(expr),
+        ExpressionStatement::Infix { left, right, .. }
+        | ExpressionStatement::Index { left, index: right }
+        | ExpressionStatement::Assign {
+            target: left,
+            value: right,
+            ..
+        }
+        | ExpressionStatement::Range {
+            start: left,
+            end: right,
+        } => {
+            ungroup_expression(left);
+            ungroup_expression(right);
+        }
+        ExpressionStatement::If {
+            condition,
+            outcome,
+            alternate,
+        } => {
+            ungroup_expression(condition);
+            ungroup_statements(outcome);
+            ungroup_statements(alternate);
+        }
+        ExpressionStatement::Fn { body, .. } => ungroup_statements(body),
+        ExpressionStatement::Call { caller, args } => {
+            ungroup_expression(caller);
+            args.iter_mut().for_each(ungroup_expression);
+        }
+        ExpressionStatement::Slice { left, start, end } => {
+            ungroup_expression(left);
+            start.iter_mut().for_each(|expr| ungroup_expression(expr));
+            end.iter_mut().for_each(|expr| ungroup_expression(expr));
+        }
+        ExpressionStatement::Array(elements) => elements.iter_mut().for_each(ungroup_expression),
+        ExpressionStatement::Hash(pairs) => {
+            for (key, value) in pairs.iter_mut() {
+                ungroup_expression(key);
+                ungroup_expression(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Program;
+    use crate::span::Span;
+
+    fn parse(input: &str) -> Program {
+        let (program, errors) = Parser::new(Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "{input}: {errors:?}");
+        program
+    }
+
+    fn node<T>(node: T) -> Spanned<T> {
+        Spanned::new(node, Span::default())
+    }
+
+    #[test]
+    fn test_display() {
+        let testcases = [
+            ("let a=1+2*3", "let a = 1 + 2 * 3;\n"),
+            ("(1+2)*3", "(1 + 2) * 3\n"),
+            ("-a**2", "-a ** 2\n"),
+            ("(-a)**2", "(-a) ** 2\n"),
+            ("2**3**2", "2 ** 3 ** 2\n"),
+            ("!  true", "!true\n"),
+            ("a[1:]", "a[1:]\n"),
+            ("a[:2]+b.c(1,2)", "a[:2] + b.c(1, 2)\n"),
+            ("x+=1..3", "x += 1..3\n"),
+            ("{\"a\":1.5, 2:[]}", "{\"a\": 1.5, 2: []}\n"),
+            ("\"a\\\"\\n\\u{1}\"", "\"a\\\"\\n\\u{1}\"\n"),
+            ("let f = 1e999", "let f = 1e999;\n"),
+            ("let a = 2.0", "let a = 2.0;\n"),
+            ("fn(){}", "fn() {}\n"),
+            (
+                "if a { 1 } else if b { 2 } else { 3 }",
+                "if a {\n    1\n} else if b {\n    2\n} else {\n    3\n}\n",
+            ),
+            ("if ((a)) {}", "if a {}\n"),
+            ("if (((a) + b) * c) {}", "if ((a + b) * c) {}\n"),
+            ("let a = ((1 + 2)) * (3)", "let a = (1 + 2) * 3;\n"),
+            ("(a - (b)) - ((c - d))", "a - b - (c - d)\n"),
+            ("let a = 1e300 + 1.5e-7", "let a = 1e300 + 1.5e-7;\n"),
+            (
+                "outer: while true { for i in 1..3 { break outer } }",
+                "outer: while true {\n    for i in 1..3 {\n        break outer;\n    }\n}\n",
+            ),
+            ("let a = 1 let b = 2", "let a = 1;\nlet b = 2;\n"),
+            ("a b", "a;\nb\n"),
+            ("if a {} b", "if a {}\nb\n"),
+            ("if a {}; (b)", "if a {}\nb\n"),
+            ("if a {}; (b + c) * d", "if a {};\n(b + c) * d\n"),
+            ("if a {}; -b", "if a {};\n-b\n"),
+            ("let a = 1 fn() { 1 }", "let a = 1;\n\nfn() {\n    1\n}\n"),
+        ];
+        for (input, expected) in testcases {
+            assert_eq!(parse(input).to_string(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_display_precedence() {
+        let ident = |name: &str| {
+            Box::new(node(ExpressionStatement::Identifier(
+                name.to_string(),
+                Binding::Unresolved,
+            )))
+        };
+        let infix = |left, operator, right| {
+            Box::new(node(ExpressionStatement::Infix {
+                left,
+                operator,
+                right,
+            }))
+        };
+        let testcases = [
+            (
+                infix(
+                    ident("a"),
+                    Token::Minus,
+                    infix(ident("b"), Token::Minus, ident("c")),
+                ),
+                "a - (b - c)",
+            ),
+            (
+                infix(
+                    infix(ident("a"), Token::Minus, ident("b")),
+                    Token::Minus,
+                    ident("c"),
+                ),
+                "a - b - c",
+            ),
+            (
+                infix(
+                    infix(ident("a"), Token::DoubleAsterisk, ident("b")),
+                    Token::DoubleAsterisk,
+                    ident("c"),
+                ),
+                "(a ** b) ** c",
+            ),
+            (
+                Box::new(node(ExpressionStatement::Prefix {
+                    operator: Token::Minus,
+                    right: infix(ident("a"), Token::Plus, ident("b")),
+                })),
+                "-(a + b)",
+            ),
+            (
+                Box::new(node(ExpressionStatement::Call {
+                    caller: Box::new(node(ExpressionStatement::Prefix {
+                        operator: Token::Minus,
+                        right: ident("f"),
+                    })),
+                    args: vec![],
+                })),
+                "(-f)()",
+            ),
+        ];
+        for (expr, expected) in testcases {
+            assert_eq!(expr.node.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let testcases = [
+            include_str!("../tests/scripts/fib.mk"),
+            "let a = [1, 2.5, \"s\", true, {1: 2}][0]; a",
+            "let f = fn(a, b) { return a * (b - 1) }; f(1, 2)(3)",
+            "let x = 1 x = x << 2 | 3 & ~4 ^ 5 >> 1; x %= 3",
+            "a.b.c = a[1][2:3] == b != c < d <= e > f >= g",
+            "for x in 1..(2..3) { if x { continue } else { return 1 } }",
+            "while (a) { let b = if (c) { d } else { e }; b }",
+            "if ((a) + b) { c }",
+            "let s = r\"raw\\n\" + \"\\t\\\\\"",
+            "-1 - -1 - (-1) ** 2 - 1.5e3 - 0.1",
+            "fn() {}\n(1)",
+            "l: for i in [] { while false { continue l } }",
+            "let a = ((1 + 2)) * (3) - (-(4)) ** ((5)); (a)",
+            "(fn(x) { x })(1) + (if a { 1 } else { 2 }) * (a = 2)",
+            "(a.b)[(1)] = ((c)); ((f))(); (1..2)",
+            "let x = 1e300 * 2.5e-300 - 1e-5",
+        ];
+        for input in testcases {
+            let program = parse(input).ungrouped();
+            let output = program.to_string();
+            assert_eq!(parse(&output).ungrouped(), program, "{output}");
+            assert_eq!(parse(&output).to_string(), output);
+        }
+        assert_eq!(
+            parse(include_str!("../tests/scripts/fib.mk")).to_string(),
+            include_str!("../tests/scripts/fib.mk")
+        );
+    }
+}
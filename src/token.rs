@@ -1,13 +1,25 @@
+use crate::error::LexError;
+
+use std::fmt;
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     Eof,
-    Illegal,
+    Illegal(LexError),
 
     Plus,
     Minus,
     Asterisk,
     Slash,
+    Percent,
+    DoubleAsterisk,
+    DoubleSlash,
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
+    PercentAssign,
     Equal,
     NotEqual,
     Bang,
@@ -15,8 +27,17 @@ pub enum Token {
     Lt,
     Gte,
     Lte,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
 
     Comma,
+    Dot,
+    DotDot,
+    Colon,
     Semicolon,
     LParen,
     RParen,
@@ -30,9 +51,123 @@ pub enum Token {
     Return,
     Let,
     Fn,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
 
     Ident(String),
+    Int(i64),
     Num(f64),
+    Str(String),
     True,
     False,
 }
+
+// Source text of the token
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Self::Eof => "end of input",
+            Self::Illegal(e) => return write!(f, "{}", e),
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Asterisk => "*",
+            Self::Slash => "/",
+            Self::Percent => "%",
+            Self::DoubleAsterisk => "**",
+            Self::DoubleSlash => "//",
+            Self::Assign => "=",
+            Self::PlusAssign => "+=",
+            Self::MinusAssign => "-=",
+            Self::AsteriskAssign => "*=",
+            Self::SlashAssign => "/=",
+            Self::PercentAssign => "%=",
+            Self::Equal => "==",
+            Self::NotEqual => "!=",
+            Self::Bang => "!",
+            Self::Gt => ">",
+            Self::Lt => "<",
+            Self::Gte => ">=",
+            Self::Lte => "<=",
+            Self::Ampersand => "&",
+            Self::Pipe => "|",
+            Self::Caret => "^",
+            Self::Tilde => "~",
+            Self::ShiftLeft => "<<",
+            Self::ShiftRight => ">>",
+            Self::Comma => ",",
+            Self::Dot => ".",
+            Self::DotDot => "..",
+            Self::Colon => ":",
+            Self::Semicolon => ";",
+            Self::LParen => "(",
+            Self::RParen => ")",
+            Self::LCurlyBracket => "{",
+            Self::RCurlyBracket => "}",
+            Self::LSquareBracket => "[",
+            Self::RSquareBracket => "]",
+            Self::If => "if",
+            Self::Else => "else",
+            Self::Return => "return",
+            Self::Let => "let",
+            Self::Fn => "fn",
+            Self::While => "while",
+            Self::For => "for",
+            Self::In => "in",
+            Self::Break => "break",
+            Self::Continue => "continue",
+            Self::Ident(name) => name,
+            Self::Int(num) => return write!(f, "{}", num),
+            Self::Num(num) => return write!(f, "{}", number_literal(*num)),
+            Self::Str(s) => return write!(f, "{}", string_literal(s)),
+            Self::True => "true",
+            Self::False => "false",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// Shortest text that reads back as the float, without a trailing .0 so 5.0 is
+// written as 5. Very large and very small magnitudes use an exponent
+pub fn format_number(num: f64) -> String {
+    let magnitude = num.abs();
+    if magnitude.is_finite() && (magnitude >= 1e21 || (magnitude != 0.0 && magnitude < 1e-6)) {
+        format!("{:e}", num)
+    } else {
+        num.to_string()
+    }
+}
+
+// Float literal that lexes back to the same value: it always has a fraction
+// or an exponent, so it is not taken for an integer
+pub fn number_literal(num: f64) -> String {
+    if num.is_infinite() {
+        return if num > 0.0 { "1e999" } else { "-1e999" }.to_string();
+    }
+    let literal = format_number(num);
+    if literal.contains(['.', 'e']) || num.is_nan() {
+        literal
+    } else {
+        literal + ".0"
+    }
+}
+
+// Double quoted string literal, with escapes for the quote, backslash and
+// control characters
+pub fn string_literal(s: &str) -> String {
+    let mut literal = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_control() => literal.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
@@ -0,0 +1,678 @@
+// Stack machine running the bytecode of the compiler module. Every frame has
+// a window of local slots and of the operand stack, both shared by all frames
+// so a call does not allocate
+use crate::compiler::{Capture, CompiledFunction, Op};
+use crate::error::{InterpreterError, RuntimeError};
+use crate::evaluator::DEFAULT_MAX_CALL_DEPTH;
+use crate::object::{self, Environment, Object};
+use crate::span::Span;
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+// Variable captured by a closure. It stays in the slot of the frame that
+// declared it while the slot is in scope, then its value moves in here
+#[derive(Debug)]
+pub enum Upvalue {
+    // Index into the vm's local slots
+    Open(usize),
+    Closed(Object),
+}
+
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("params", &self.function.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // First local slot of the frame
+    base: usize,
+    // Height of the operand stack when the frame was entered
+    stack_base: usize,
+    // Calls the frame is nested in, 0 for a script
+    depth: usize,
+}
+
+pub struct Vm {
+    globals: Rc<RefCell<Environment>>,
+    stack: Vec<Object>,
+    locals: Vec<Object>,
+    frames: Vec<Frame>,
+    // Upvalues still pointing at a local slot, ordered by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    max_call_depth: usize,
+}
+
+impl Vm {
+    pub fn new(globals: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            globals,
+            stack: vec![],
+            locals: vec![],
+            frames: vec![],
+            open_upvalues: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    // Tail calls do not count towards the depth
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    // Run a compiled script, its value is the one of its last statement
+    pub fn run(&mut self, script: Rc<CompiledFunction>) -> Result<Object, InterpreterError> {
+        let closure = Rc::new(Closure {
+            function: script,
+            upvalues: vec![],
+        });
+        let entry = self.frames.len();
+        self.push_frame(closure, vec![], 0);
+        self.execute(entry)
+    }
+
+    // Call a function or builtin with already evaluated arguments
+    pub fn call(
+        &mut self,
+        function: Object,
+        args: Vec<Object>,
+    ) -> Result<Object, InterpreterError> {
+        match function {
+            Object::Closure(closure) => {
+                Self::check_arity(&closure, &args)?;
+                if self.max_call_depth == 0 {
+                    return Err(RuntimeError::CallDepthExceeded(self.max_call_depth).into());
+                }
+                let entry = self.frames.len();
+                self.push_frame(closure, args, 1);
+                self.execute(entry)
+            }
+            Object::Builtin(builtin) => Ok((builtin.func)(&args)?),
+            v => Err(RuntimeError::NotCallable(v).into()),
+        }
+    }
+
+    // Run until the frame at entry returns. On error every frame from entry
+    // up is dropped, leaving the vm ready for the next run
+    fn execute(&mut self, entry: usize) -> Result<Object, InterpreterError> {
+        loop {
+            match self.step() {
+                Ok(None) => {}
+                Ok(Some(v)) if self.frames.len() == entry => return Ok(v),
+                Ok(Some(v)) => self.stack.push(v),
+                Err(e) => {
+                    let span = self.span();
+                    self.unwind(entry);
+                    return Err(InterpreterError::Runtime(e, Some(span)));
+                }
+            }
+        }
+    }
+
+    fn unwind(&mut self, entry: usize) {
+        let (base, stack_base) = (self.frames[entry].base, self.frames[entry].stack_base);
+        self.close_upvalues(base);
+        self.locals.truncate(base);
+        self.stack.truncate(stack_base);
+        self.frames.truncate(entry);
+    }
+
+    // Source of the instruction the running frame executed last
+    fn span(&self) -> Span {
+        let frame = self.frame();
+        frame.closure.function.chunk.spans[frame.ip - 1]
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("running frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("running frame")
+    }
+
+    fn push(&mut self, v: Object) {
+        self.stack.push(v);
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("operand stack underflow")
+    }
+
+    fn peek(&self) -> &Object {
+        self.stack.last().expect("operand stack underflow")
+    }
+
+    fn constant(&self, index: u16) -> Object {
+        self.frame().closure.function.chunk.constants[index as usize].clone()
+    }
+
+    fn local(&self, slot: u16) -> usize {
+        self.frame().base + slot as usize
+    }
+
+    fn upvalue(&self, index: u16) -> Rc<RefCell<Upvalue>> {
+        self.frame().closure.upvalues[index as usize].clone()
+    }
+
+    // Execute one instruction, returns the result of a frame that returned
+    fn step(&mut self) -> Result<Option<Object>, RuntimeError> {
+        let frame = self.frame_mut();
+        let op = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+
+        match op {
+            Op::Constant(index) => self.push(self.constant(index)),
+            Op::Null => self.push(Object::Null),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Dup => self.push(self.peek().clone()),
+            Op::Dup2 => {
+                let len = self.stack.len();
+                self.stack.extend_from_within(len - 2..);
+            }
+            Op::GetLocal(slot) => self.push(self.locals[self.local(slot)].clone()),
+            Op::SetLocal(slot) => {
+                let i = self.local(slot);
+                self.locals[i] = self.peek().clone();
+            }
+            Op::GetUpvalue(index) => {
+                let v = match &*self.upvalue(index).borrow() {
+                    Upvalue::Open(i) => self.locals[*i].clone(),
+                    Upvalue::Closed(v) => v.clone(),
+                };
+                self.push(v);
+            }
+            Op::SetUpvalue(index) => {
+                let v = self.peek().clone();
+                match &mut *self.upvalue(index).borrow_mut() {
+                    Upvalue::Open(i) => self.locals[*i] = v,
+                    Upvalue::Closed(closed) => *closed = v,
+                }
+            }
+            Op::GetGlobal(index) => {
+                let v = self.globals.borrow().get_global(index as usize)?;
+                self.push(v);
+            }
+            Op::SetGlobal(index) => {
+                let v = self.peek().clone();
+                self.globals.borrow_mut().set_global(index as usize, v)?;
+            }
+            Op::DefineGlobal(index) => {
+                let v = self.peek().clone();
+                self.globals.borrow_mut().define_global(index as usize, v)?;
+            }
+            Op::CloseUpvalues(slot) => self.close_upvalues(self.local(slot)),
+            Op::Not => {
+                let v = self.pop();
+                self.push(Object::Boolean(!v.is_truthy()));
+            }
+            Op::Neg => {
+                let v = self.pop();
+                self.push((-v)?);
+            }
+            Op::BitNot => {
+                let v = self.pop();
+                self.push(v.bit_not()?);
+            }
+            Op::Jump(to) => self.frame_mut().ip = to as usize,
+            Op::JumpIfFalse(to) => {
+                if !self.pop().is_truthy() {
+                    self.frame_mut().ip = to as usize;
+                }
+            }
+            Op::Array(n) => {
+                let elements = self.stack.split_off(self.stack.len() - n as usize);
+                self.push(Object::new_array(elements));
+            }
+            Op::Hash(n) => {
+                let values = self.stack.split_off(self.stack.len() - 2 * n as usize);
+                let mut hash = object::Hash::new();
+                let mut values = values.into_iter();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    hash.insert(key, value)?;
+                }
+                self.push(Object::new_hash(hash));
+            }
+            Op::Index => {
+                let index = self.pop();
+                let left = self.pop();
+                self.push(left.index(&index)?);
+            }
+            Op::SetIndex => {
+                let v = self.pop();
+                let index = self.pop();
+                let left = self.pop();
+                left.set_index(&index, v.clone())?;
+                self.push(v);
+            }
+            Op::Slice { start, end } => {
+                let end = if end { Some(self.pop()) } else { None };
+                let start = if start { Some(self.pop()) } else { None };
+                let left = self.pop();
+                self.push(left.slice(start.as_ref(), end.as_ref())?);
+            }
+            Op::GetField(index) => match self.pop() {
+                left @ Object::Hash(_) => self.push(left.index(&self.constant(index))?),
+                v => return Err(RuntimeError::NoFields(v)),
+            },
+            Op::SetField(index) => {
+                let v = self.pop();
+                match self.pop() {
+                    left @ Object::Hash(_) => left.set_index(&self.constant(index), v.clone())?,
+                    left => return Err(RuntimeError::NoFields(left)),
+                }
+                self.push(v);
+            }
+            Op::Range => {
+                let end = self.pop();
+                let start = self.pop();
+                let (start, end) = Self::range(start, end)?;
+                self.push(Object::new_array(
+                    (start..end).map(Object::Integer).collect(),
+                ));
+            }
+            Op::CheckRange => {
+                let len = self.stack.len();
+                Self::range(self.stack[len - 2].clone(), self.stack[len - 1].clone())?;
+            }
+            Op::IterValues => {
+                let v = self.pop();
+                self.push(Object::new_array(v.iter_values()?));
+            }
+            Op::ForNext { slot, exit } => self.for_next(slot, exit),
+            Op::Closure(index) => self.closure(index),
+            Op::Call(argc) => return self.call_value(argc, false),
+            Op::TailCall(argc) => return self.call_value(argc, true),
+            Op::Return => {
+                let v = self.pop();
+                self.leave_frame();
+                return Ok(Some(v));
+            }
+            _ => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                self.push(Self::binary(op, lhs, rhs)?);
+            }
+        }
+        Ok(None)
+    }
+
+    fn binary(op: Op, lhs: Object, rhs: Object) -> Result<Object, RuntimeError> {
+        match op {
+            Op::Add => lhs + rhs,
+            Op::Sub => lhs - rhs,
+            Op::Mul => lhs * rhs,
+            Op::Div => lhs / rhs,
+            Op::Mod => lhs % rhs,
+            Op::Pow => lhs.pow(rhs),
+            Op::FloorDiv => lhs.floor_div(rhs),
+            Op::BitAnd => lhs & rhs,
+            Op::BitOr => lhs | rhs,
+            Op::BitXor => lhs ^ rhs,
+            Op::Shl => lhs << rhs,
+            Op::Shr => lhs >> rhs,
+            Op::Equal => Ok(Object::Boolean(lhs.equals(&rhs))),
+            Op::NotEqual => Ok(Object::Boolean(!lhs.equals(&rhs))),
+            Op::Gt => Ok(Object::Boolean(
+                lhs.compare(&rhs)? == Some(Ordering::Greater),
+            )),
+            Op::Gte => Ok(Object::Boolean(matches!(
+                lhs.compare(&rhs)?,
+                Some(Ordering::Greater | Ordering::Equal)
+            ))),
+            Op::Lt => Ok(Object::Boolean(lhs.compare(&rhs)? == Some(Ordering::Less))),
+            Op::Lte => Ok(Object::Boolean(matches!(
+                lhs.compare(&rhs)?,
+                Some(Ordering::Less | Ordering::Equal)
+            ))),
+            op => unreachable!("{:?} is not a binary operator", op),
+        }
+    }
+
+    fn range(start: Object, end: Object) -> Result<(i64, i64), RuntimeError> {
+        match (start, end) {
+            (Object::Integer(start), Object::Integer(end)) => Ok((start, end)),
+            (left, right) => Err(RuntimeError::TypeMismatch {
+                operator: "..",
+                left,
+                right,
+            }),
+        }
+    }
+
+    // The loop's state is the end of a range and its next number, or an
+    // array of values and the index of the next one
+    fn for_next(&mut self, slot: u16, exit: u32) {
+        let i = self.local(slot);
+        let next = match (&self.locals[i], &self.locals[i + 1]) {
+            (Object::Integer(end), Object::Integer(n)) => (n < end).then_some(Object::Integer(*n)),
+            (Object::Array(values), Object::Integer(n)) => {
+                values.borrow().get(*n as usize).cloned()
+            }
+            state => unreachable!("invalid for loop state {:?}", state),
+        };
+        match next {
+            Some(v) => {
+                if let Object::Integer(n) = &mut self.locals[i + 1] {
+                    *n += 1;
+                }
+                self.push(v);
+            }
+            None => self.frame_mut().ip = exit as usize,
+        }
+    }
+
+    fn closure(&mut self, index: u16) {
+        let (enclosing, base) = (self.frame().closure.clone(), self.frame().base);
+        let function = enclosing.function.chunk.functions[index as usize].clone();
+        let upvalues = function
+            .captures
+            .iter()
+            .map(|capture| match *capture {
+                Capture::Local(slot) => self.capture_upvalue(base + slot as usize),
+                Capture::Upvalue(index) => enclosing.upvalues[index as usize].clone(),
+            })
+            .collect();
+        self.push(Object::Closure(Rc::new(Closure { function, upvalues })));
+    }
+
+    // Closures capturing the same slot share its upvalue
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let at = self
+            .open_upvalues
+            .partition_point(|upvalue| Self::open_slot(upvalue) < slot);
+        if let Some(upvalue) = self.open_upvalues.get(at) {
+            if Self::open_slot(upvalue) == slot {
+                return upvalue.clone();
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.insert(at, upvalue.clone());
+        upvalue
+    }
+
+    fn open_slot(upvalue: &RefCell<Upvalue>) -> usize {
+        match *upvalue.borrow() {
+            Upvalue::Open(slot) => slot,
+            Upvalue::Closed(_) => unreachable!("closed upvalue in the open list"),
+        }
+    }
+
+    // Move the values of slots from `from` up into the upvalues capturing them
+    fn close_upvalues(&mut self, from: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = Self::open_slot(upvalue);
+            if slot < from {
+                break;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(self.locals[slot].clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    // Call the callee below the top argc values. A tail call replaces the
+    // running frame, and returns its result when the callee is a builtin
+    fn call_value(&mut self, argc: u8, tail: bool) -> Result<Option<Object>, RuntimeError> {
+        let args = self.stack.split_off(self.stack.len() - argc as usize);
+        match self.pop() {
+            Object::Closure(closure) => {
+                Self::check_arity(&closure, &args)?;
+                let depth = self.frame().depth;
+                if tail {
+                    self.leave_frame();
+                    self.push_frame(closure, args, depth);
+                } else {
+                    if depth >= self.max_call_depth {
+                        return Err(RuntimeError::CallDepthExceeded(self.max_call_depth));
+                    }
+                    self.push_frame(closure, args, depth + 1);
+                }
+                Ok(None)
+            }
+            Object::Builtin(builtin) => {
+                let v = (builtin.func)(&args)?;
+                if tail {
+                    self.leave_frame();
+                    return Ok(Some(v));
+                }
+                self.push(v);
+                Ok(None)
+            }
+            v => Err(RuntimeError::NotCallable(v)),
+        }
+    }
+
+    fn check_arity(closure: &Closure, args: &[Object]) -> Result<(), RuntimeError> {
+        let expected = closure.function.params.len();
+        if expected != args.len() {
+            return Err(RuntimeError::ArgumentCount {
+                expected,
+                found: args.len(),
+            });
+        }
+        Ok(())
+    }
+
+    // Arguments take the first slots of the frame
+    fn push_frame(&mut self, closure: Rc<Closure>, args: Vec<Object>, depth: usize) {
+        let base = self.locals.len();
+        self.locals.extend(args);
+        self.locals
+            .resize(base + closure.function.slot_count, Object::Null);
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base,
+            stack_base: self.stack.len(),
+            depth,
+        });
+    }
+
+    fn leave_frame(&mut self) {
+        let frame = self.frames.pop().expect("running frame");
+        self.close_upvalues(frame.base);
+        self.locals.truncate(frame.base);
+        self.stack.truncate(frame.stack_base);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler;
+    use crate::evaluator::Evaluator;
+    use crate::{lexer, parser, resolver};
+
+    // A low call depth keeps the evaluator within the test thread's stack
+    const MAX_CALL_DEPTH: usize = 100;
+
+    fn run(
+        input: &str,
+    ) -> (
+        Result<Object, InterpreterError>,
+        Result<Object, InterpreterError>,
+    ) {
+        let (mut program, errors) = parser::Parser::new(lexer::Lexer::new(input)).parse_program();
+        assert!(errors.is_empty(), "{input}: {:?}", errors);
+        let env = Environment::new(None);
+        let mut vm = Vm::new(env.clone());
+        vm.set_max_call_depth(MAX_CALL_DEPTH);
+        let compiled = {
+            let mut env = env.borrow_mut();
+            resolver::resolve(&mut program, &mut env)
+                .and_then(|_| compiler::compile(&program, &mut env.variables))
+        };
+        let executed = compiled.and_then(|script| vm.run(script));
+        let mut evaluator = Evaluator::new();
+        evaluator.set_max_call_depth(MAX_CALL_DEPTH);
+        let evaluated = evaluator.eval(program, Environment::new(None));
+        (evaluated, executed)
+    }
+
+    // Every program gives the same value, or the same error at the same
+    // location, on both engines
+    #[test]
+    fn test_same_as_evaluator() {
+        let testcases = vec![
+            "1 + 2 * 3 - 4 / 2",
+            "7 // 2 + 7 % -3 + 2 ** 10 + (5 & 3 | 8 ^ 1) + (1 << 4 >> 2) + ~3",
+            "\"a\" + 1 + \"b\" + 2.5",
+            "[1 < 2, 2 <= 2, 3 > 4, 1.5 >= 1, 1 == 1.0, \"a\" != \"b\", !0, -(2)]",
+            "let a = 5; if a > 3 { a * 2 } else { 0 }",
+            "if false { 1 }",
+            "let a = [1, 2, 3]; a[1] = 5; a[-1] += 1; [a, a[1:], a[:-1], \"hello\"[1:3]]",
+            "let h = {\"a\": 1, 2: \"b\"}; h.a += 1; h.c = 3; [h, h[2], h[\"d\"]]",
+            "let x = 1; x = x + 1; x *= 3",
+            "let add = fn(a, b) { a + b }; add(1, add(2, 3))",
+            "let fib = fn(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+            "let counter = fn() { let c = 0; fn() { c += 1 } }; let a = counter(); let b = counter(); a(); a(); [a(), b()]",
+            "let make = fn(x) { let get = fn() { x }; let set = fn(v) { x = v }; [get, set] };
+             let p = make(1); p[1](7); p[0]()",
+            "let f = fn() { let a = 1; let g = fn() { let h = fn() { a += 1 }; h() }; g(); g(); a }; f()",
+            "let fs = []; let h = {}; for i in 0..3 { h[i] = fn() { i * 10 } }; [h[0](), h[2]()]",
+            "let f = fn(x) { let r = 0; for x in [x, x + 1] { let g = fn() { fn() { x } }; if true { let x = 0; r += g()() + x } }; [x, r] }; f(5)",
+            "let s = \"\"; for x in [1, \"a\", true] { s = s + str(x) + \" \" }; for c in \"bc\" { s += c }; for k in {\"k\": 1} { s += k }; s",
+            "let n = 0; while n < 10 { n += 1; if n % 2 == 0 { continue }; if n > 7 { break } }; n",
+            "let s = 0; outer: for i in 0..5 { for j in 0..5 { if j > i { continue outer }; if i == 4 { break outer }; s += j } }; s",
+            "let f = fn() { for i in 0..10 { if i == 3 { return i } }; 99 }; f()",
+            "let f = fn(x) { if x { if x > 1 { return \"big\" } }; \"small\" }; [f(2), f(1)]",
+            "let f = fn() { 1 + (if true { return 5 } else { 2 }) }; f()",
+            "let f = fn() { let a = [1, 2]; for x in a { a[0] = 10 }; a }; f()",
+            "let count = fn(n, acc) { if n == 0 { acc } else { count(n - 1, acc + 1) } }; count(100000, 0)",
+            "let even = fn(n) { if n == 0 { true } else { odd(n - 1) } };
+             let odd = fn(n) { if n == 0 { false } else { even(n - 1) } }; even(10001)",
+            "let f = fn() { return len([1, 2]) }; f()",
+            "let f = fn(n) { if n > 0 { if n > 1 { return } return; } n }; [f(2), f(1), f(0)]",
+            "let a = 0..4; a",
+            "while false { 1 }",
+            "let a = if true { let b = 1 }",
+            "len(\"héllo\") + num(\"2\")",
+            "type(fn() { 1 })",
+            // Errors
+            "1 + missing",
+            "let a = 1;\nlet b = a / 0",
+            "let f = fn() {\n  [1][5]\n};\nf()",
+            "let a = 1; let a = 2",
+            "let f = fn(a) { a }; f(1, 2)",
+            "let f = 5; f()",
+            "let f = fn(a) { f(a) + 1 }; f(1)",
+            "let t = fn(n) { if n == 0 { 1 + true } else { t(n - 1) } }; t(5)",
+            "x = 1",
+            "let h = 5; h.a = 1",
+            "for x in\n  5 { }",
+            "for x in 1..\"a\" { }",
+            "{[1]: 2}",
+            "len(1, 2)",
+            "let f = fn(x) { let x = 1 }; f(1)",
+        ];
+
+        for input in testcases.into_iter() {
+            let (evaluated, executed) = run(input);
+            assert_eq!(executed, evaluated, "{input}");
+        }
+    }
+
+    // Errors the resolver finds before running anything, both engines report
+    // them the same
+    #[test]
+    fn test_bare_return() {
+        let input =
+            "let f = fn(n) { if n { if n > 1 { return } 1 } else { return; } }; [f(2), f(0)]";
+        let (_, executed) = run(input);
+        assert_eq!(
+            executed,
+            Ok(Object::new_array(vec![Object::Null, Object::Null]))
+        );
+    }
+
+    #[test]
+    fn test_static_errors() {
+        let testcases = vec![
+            ("return 1", RuntimeError::ReturnOutsideFunction, (1, 1)),
+            (
+                "let a = 1;\nif a { break }",
+                RuntimeError::OutsideLoop("break"),
+                (2, 8),
+            ),
+            (
+                "for i in [1] { let f = fn() { continue }; f() }",
+                RuntimeError::OutsideLoop("continue"),
+                (1, 31),
+            ),
+            (
+                "for x in [1] { break outer }",
+                RuntimeError::UndefinedLabel("outer".to_string()),
+                (1, 16),
+            ),
+        ];
+
+        for (input, expected, (line, column)) in testcases.into_iter() {
+            match run(input) {
+                (Err(e1), Err(InterpreterError::Syntax(errors))) => {
+                    assert_eq!(e1, InterpreterError::Syntax(errors.clone()), "{input}");
+                    match &errors[..] {
+                        [InterpreterError::Resolve(e, span)] => {
+                            assert_eq!(*e, expected, "{input}");
+                            assert_eq!((span.line, span.column), (line, column), "{input}");
+                        }
+                        errors => panic!("Expected one error for {input}, got {:?}", errors),
+                    }
+                }
+                results => panic!("Expected errors for {input}, got {:?}", results),
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let env = Environment::new(None);
+        let mut vm = Vm::new(env.clone());
+        vm.set_max_call_depth(50);
+        let compile = |input: &str| {
+            let (mut program, _) = parser::Parser::new(lexer::Lexer::new(input)).parse_program();
+            let mut env = env.borrow_mut();
+            resolver::resolve(&mut program, &mut env).unwrap();
+            compiler::compile(&program, &mut env.variables).unwrap()
+        };
+        let program = "let f = fn(n) { if n == 0 { 0 } else { 1 + f(n - 1) } }; f(49)";
+        assert_eq!(vm.run(compile(program)), Ok(Object::Integer(49)));
+
+        let v = vm.run(compile("f(50)"));
+        assert!(
+            matches!(
+                v,
+                Err(InterpreterError::Runtime(
+                    RuntimeError::CallDepthExceeded(50),
+                    Some(_)
+                ))
+            ),
+            "{:?}",
+            v
+        );
+
+        // Frames are unwound after the error
+        assert_eq!(vm.run(compile("f(49)")), Ok(Object::Integer(49)));
+        let f = vm.globals.borrow().variables.get("f").cloned().unwrap();
+        assert_eq!(vm.call(f, vec![Object::Integer(3)]), Ok(Object::Integer(3)));
+    }
+}
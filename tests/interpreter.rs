@@ -0,0 +1,418 @@
+use interpreter::{arg, Engine, Interpreter, InterpreterError, Object, ParseError, RuntimeError};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn test_eval_str() {
+    let mut interpreter = Interpreter::new();
+    assert_eq!(interpreter.eval_str("1 + 2 * 3"), Ok(Object::Integer(7)));
+
+    // Definitions are kept between calls
+    interpreter
+        .eval_str("let square = fn(x) { x * x };")
+        .unwrap();
+    assert_eq!(
+        interpreter.eval_str("square(1.5)"),
+        Ok(Object::Number(2.25))
+    );
+}
+
+#[test]
+fn test_eval_file() {
+    let mut interpreter = Interpreter::new();
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scripts/fib.mk");
+    assert_eq!(interpreter.eval_file(path), Ok(Object::Integer(55)));
+    assert_eq!(
+        interpreter.call_function("fib", &[Object::Integer(7)]),
+        Ok(Object::Integer(13))
+    );
+
+    assert!(matches!(
+        interpreter.eval_file("tests/scripts/missing.mk"),
+        Err(InterpreterError::Io { .. })
+    ));
+}
+
+#[test]
+fn test_globals() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_global("limit", 10_i64);
+    interpreter.set_global("names", vec!["a", "b"]);
+    assert_eq!(
+        interpreter.eval_str("limit * len(names)"),
+        Ok(Object::Integer(20))
+    );
+
+    interpreter.eval_str(r#"let greeting = "hi""#).unwrap();
+    assert_eq!(
+        interpreter.get_global("greeting"),
+        Some(Object::String("hi".to_string()))
+    );
+    assert_eq!(interpreter.get_global("undefined"), None);
+
+    let names: Vec<String> = interpreter
+        .globals()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(names, vec!["greeting", "limit", "names"]);
+}
+
+#[test]
+fn test_call_function() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .eval_str("let add = fn(a, b) { a + b }")
+        .unwrap();
+    assert_eq!(
+        interpreter.call_function("add", &[Object::from("a"), Object::from(1_i64)]),
+        Ok(Object::String("a1".to_string()))
+    );
+    assert_eq!(
+        interpreter.call_function("len", &[Object::from("abc")]),
+        Ok(Object::Integer(3))
+    );
+    assert!(matches!(
+        interpreter.call_function("add", &[]),
+        Err(InterpreterError::Runtime(
+            RuntimeError::ArgumentCount {
+                expected: 2,
+                found: 0
+            },
+            _
+        ))
+    ));
+    assert!(matches!(
+        interpreter.call_function("nope", &[]),
+        Err(InterpreterError::Runtime(
+            RuntimeError::UndefinedVariable(_),
+            _
+        ))
+    ));
+}
+
+#[test]
+fn test_register_fn() {
+    let mut interpreter = Interpreter::new();
+    let total = Rc::new(RefCell::new(0.0));
+    let sum = total.clone();
+    interpreter.register_fn("add_to_total", move |args: &[Object]| {
+        *sum.borrow_mut() += arg::<f64>(args, 0)?;
+        Ok::<_, RuntimeError>(*sum.borrow())
+    });
+
+    assert_eq!(
+        interpreter.eval_str("add_to_total(1); add_to_total(2.5)"),
+        Ok(Object::Number(3.5))
+    );
+    assert_eq!(*total.borrow(), 3.5);
+
+    // A missing argument is an error rather than a panic in the callback
+    assert_eq!(
+        interpreter
+            .eval_str("add_to_total()")
+            .map_err(|e| e.to_string()),
+        Err("Expected 1 arguments, got 0".to_string())
+    );
+    assert_eq!(*total.borrow(), 3.5);
+
+    let builtins = interpreter.builtins();
+    assert!(builtins.contains(&"add_to_total".to_string()));
+    assert!(builtins.contains(&"len".to_string()));
+    assert!(builtins.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn test_errors() {
+    let mut interpreter = Interpreter::new();
+    let source = "let = 1;\nlet b = (2;";
+    match interpreter.eval_str(source) {
+        Err(InterpreterError::Syntax(errors)) => {
+            assert_eq!(errors.len(), 2);
+            assert!(matches!(
+                errors[0],
+                InterpreterError::Parse(ParseError::UnexpectedToken { .. }, _)
+            ));
+        }
+        result => panic!("Expected syntax errors, got {:?}", result),
+    }
+
+    let source = "let a = 1;\nlet b = a / 0";
+    let err = interpreter.eval_str(source).unwrap_err();
+    assert!(matches!(
+        err,
+        InterpreterError::Runtime(RuntimeError::DivisionByZero, Some(_))
+    ));
+    assert_eq!(
+        err.render(source),
+        "Division by zero at line 2, column 9\n2 | let b = a / 0\n  |         ^^^^^"
+    );
+}
+
+#[test]
+fn test_cycles() {
+    for engine in [Engine::Evaluator, Engine::Vm] {
+        let mut interpreter = Interpreter::with_engine(engine);
+        interpreter
+            .eval_str("let a = [1]; a[0] = a; let b = [1]; b[0] = b;")
+            .unwrap();
+        assert_eq!(
+            interpreter.eval_str("[a == a, a == b, a != [a]]"),
+            Ok(Object::from(vec![true, true, false]))
+        );
+
+        // Errors show the values involved without recursing into them
+        match interpreter.eval_str("a + 1") {
+            Err(InterpreterError::Runtime(err, _)) => assert_eq!(
+                err.to_string(),
+                "Unsupported operand types for +: [[...]] and 1"
+            ),
+            result => panic!("Expected a runtime error, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn test_resolve_errors() {
+    for engine in [Engine::Evaluator, Engine::Vm] {
+        let mut interpreter = Interpreter::with_engine(engine);
+        interpreter.eval_str("let a = 1").unwrap();
+
+        // Nothing runs when the source uses an undeclared name
+        assert!(matches!(
+            interpreter.eval_str("let b = a; fn() { missing }"),
+            Err(InterpreterError::Syntax(errors))
+                if matches!(errors[..], [InterpreterError::Resolve(RuntimeError::UndefinedVariable(_), _)])
+        ));
+        assert_eq!(interpreter.get_global("b"), None);
+
+        // Names defined by earlier sources are known
+        assert!(matches!(
+            interpreter.eval_str("let a = 2"),
+            Err(InterpreterError::Syntax(errors))
+                if matches!(errors[..], [InterpreterError::Resolve(RuntimeError::AlreadyDeclared(_), _)])
+        ));
+        assert_eq!(
+            interpreter.eval_str("let f = fn() { a + b }; let b = 2; f()"),
+            Ok(Object::Integer(3))
+        );
+    }
+}
+
+// Both engines reject a break or continue outside a loop before running, even
+// when it is never reached
+#[test]
+fn test_jump_outside_loop() {
+    let testcases = [
+        (
+            "if false { break }; println(\"ok\")",
+            "'break' outside loop at line 1, column 12",
+        ),
+        (
+            "let f = fn() { if false { continue } 1 }; println(f())",
+            "'continue' outside loop at line 1, column 27",
+        ),
+        (
+            "let f = fn() { break }; for i in 0..1 { f() }",
+            "'break' outside loop at line 1, column 16",
+        ),
+    ];
+
+    for (input, expected) in testcases {
+        for engine in [Engine::Evaluator, Engine::Vm] {
+            let mut interpreter = Interpreter::with_engine(engine);
+            let e = interpreter.eval_str(input).unwrap_err();
+            let rendered = e.render(input);
+            assert_eq!(
+                rendered.lines().next(),
+                Some(expected),
+                "{input} {engine:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_max_call_depth() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_call_depth(20);
+    interpreter
+        .eval_str("let sum = fn(n) { if n == 0 { 0 } else { n + sum(n - 1) } };")
+        .unwrap();
+    assert_eq!(interpreter.eval_str("sum(19)"), Ok(Object::Integer(190)));
+    assert!(matches!(
+        interpreter.call_function("sum", &[Object::Integer(20)]),
+        Err(InterpreterError::Runtime(
+            RuntimeError::CallDepthExceeded(20),
+            _
+        ))
+    ));
+}
+
+#[test]
+fn test_vm_engine() {
+    let mut interpreter = Interpreter::with_engine(Engine::Vm);
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scripts/fib.mk");
+    assert_eq!(interpreter.eval_file(path), Ok(Object::Integer(55)));
+    assert_eq!(
+        interpreter.call_function("fib", &[Object::Integer(7)]),
+        Ok(Object::Integer(13))
+    );
+
+    interpreter.register_fn("double", |args: &[Object]| {
+        arg::<i64>(args, 0).map(|n| n * 2)
+    });
+    assert_eq!(
+        interpreter.eval_str("let f = fn(x) { double(x) + 1 }; f(20)"),
+        Ok(Object::Integer(41))
+    );
+    assert!(interpreter.disassemble("f(1)").unwrap().contains("Call(1)"));
+    // Disassembly is checked like a program that runs
+    assert!(matches!(
+        interpreter.disassemble("g(1)"),
+        Err(InterpreterError::Syntax(errors))
+            if matches!(errors[..], [InterpreterError::Resolve(RuntimeError::UndefinedVariable(_), _)])
+    ));
+    // Numbers given to the globals of a listing are not kept
+    let listing = interpreter.disassemble("let x = 1").unwrap();
+    assert_eq!(
+        interpreter.disassemble("let y = 1").unwrap(),
+        listing.replace("; x", "; y")
+    );
+
+    // Globals the vm numbered are shared with the host
+    interpreter
+        .eval_str("let total = 0; for i in 0..10 { total += i }")
+        .unwrap();
+    assert_eq!(interpreter.get_global("total"), Some(Object::Integer(45)));
+    interpreter.set_global("total", 1_i64);
+    interpreter.set_global("step", 2_i64);
+    assert_eq!(
+        interpreter.eval_str("total += step; total"),
+        Ok(Object::Integer(3))
+    );
+}
+
+#[test]
+fn test_is_incomplete() {
+    let testcases = vec![
+        ("1 + 2", false),
+        ("", false),
+        ("let f = fn(x) {", true),
+        ("let f = fn(x) {\n  x\n}", false),
+        ("f(1,\n", true),
+        ("[1, [2]", true),
+        ("1 +", true),
+        ("a.", true),
+        ("let a =", true),
+        ("if a { 1 } else", true),
+        ("for x in", true),
+        ("1 + 2)", false),
+        ("\"abc", false),
+        ("\"\"\"abc", true),
+        ("r\"abc", true),
+        ("\"{\"", false),
+    ];
+    for (source, expected) in testcases.into_iter() {
+        assert_eq!(Interpreter::is_incomplete(source), expected, "{source}");
+    }
+}
+
+#[test]
+fn test_tokens() {
+    assert_eq!(
+        Interpreter::tokens("let a =\n  [1.5]"),
+        "1:1 Let\n1:5 Ident(\"a\")\n1:7 Assign\n2:3 LSquareBracket\n2:4 Num(1.5)\n2:7 RSquareBracket"
+    );
+    assert_eq!(Interpreter::tokens(""), "");
+}
+
+#[test]
+fn test_format() {
+    assert_eq!(
+        Interpreter::format("let a=(1+2)*3;print( a )"),
+        Ok(String::from("let a = (1 + 2) * 3;\nprint(a)\n"))
+    );
+    assert!(matches!(
+        Interpreter::format("let = 1"),
+        Err(InterpreterError::Syntax(_))
+    ));
+
+    assert!(Interpreter::equivalent("a + (b * c)", "a + b * c"));
+    assert!(!Interpreter::equivalent("(a + b) * c", "a + b * c"));
+    assert!(!Interpreter::equivalent("let = 1", "let = 1"));
+}
+
+#[test]
+#[cfg(feature = "cli")]
+fn test_cli() {
+    use std::io::Write;
+    use std::process::{self, Command, Output, Stdio};
+    use std::{env, fs};
+
+    let run = |args: &[&str], stdin: &str| -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_interpreter"))
+            .args(args)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    for engine in ["--engine=eval", "--engine=vm"] {
+        let output = run(&[engine, "tests/scripts/args.mk", "a", "-b"], "");
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2\na\n-b\n");
+
+        let output = run(&[engine, "-e", "println(1 + 2)"], "");
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+
+        let output = run(&[engine, "-", "x"], "println(args())");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "[x]\n");
+    }
+
+    // Statuses follow sysexits.h
+    assert_eq!(run(&["-e", "let = 1"], "").status.code(), Some(65));
+    assert_eq!(run(&["-e", "1 / 0"], "").status.code(), Some(70));
+    // Rejected by the resolver before anything runs
+    let output = run(&["-e", "println(1); missing; return 2"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        run(&["tests/scripts/missing.mk"], "").status.code(),
+        Some(66)
+    );
+    assert_eq!(run(&["--nope"], "").status.code(), Some(2));
+
+    let output = run(&["-e", "1 / 0"], "");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Division by zero"));
+
+    // fmt keeps the #! line of an executable script
+    let path = env::temp_dir().join(format!("fmt-shebang-{}.mk", process::id()));
+    let path_arg = path.to_str().unwrap();
+    fs::write(&path, "#!/usr/bin/env interpreter\nlet a=1+2\nprintln( a )").unwrap();
+    assert_eq!(
+        run(&["fmt", "--check", path_arg], "").status.code(),
+        Some(1)
+    );
+    assert_eq!(run(&["fmt", path_arg], "").status.code(), Some(0));
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "#!/usr/bin/env interpreter\nlet a = 1 + 2;\nprintln(a)\n"
+    );
+    let output = run(&["fmt", "--check", path_arg], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,6 @@
+#!/usr/bin/env interpreter
+let names = args();
+println(len(names));
+for name in names {
+    println(name);
+}